@@ -0,0 +1,200 @@
+use std::io;
+use std::ffi::{CString, CStr, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bytes::{BytesMut, BufMut};
+
+use libc::{c_int, c_void, c_char};
+
+use fuse_sys::ffi::{fuse_args, fuse_mount_compat25, fuse_unmount_compat22};
+
+use crate::splice::SplicedReply;
+
+/// The ioctl, that attaches a newly opened /dev/fuse to the connection of another one.
+/// This is `_IOR(229, 0, uint32_t)` and supported since Linux 4.2.
+const FUSE_DEV_IOC_CLONE: u64 = 0x8004_e500;
+
+/// An open file descriptor of /dev/fuse, from which requests are read and to which
+/// the replies are written.
+///
+/// A connection can have several channels. The kernel hands every request to only one of them,
+/// and expects the reply on the same channel.
+#[derive(Debug)]
+pub(crate) struct Channel {
+    fd: RawFd,
+    splice: Arc<Splice>,
+}
+
+/// How replies are spliced into the channels of a connection
+#[derive(Debug)]
+pub(crate) struct Splice {
+    // Turned off for all channels, once splicing is not permitted
+    write: AtomicBool,
+    move_pages: bool,
+    max_read: usize,
+}
+
+impl Splice {
+
+    /// Replies with the data of a file are spliced into the channel, if `write` is set. Their
+    /// pages are moved instead of copied, if `move_pages` is set. The pipes are sized for
+    /// replies of up to `max_read` bytes.
+    pub(crate) fn new(write: bool, move_pages: bool, max_read: usize) -> Self {
+        Splice {
+            write: AtomicBool::new(write),
+            move_pages,
+            max_read,
+        }
+    }
+
+    /// Returns true, if replies are spliced into the channel
+    pub(crate) fn write(&self) -> bool {
+        self.write.load(Ordering::Relaxed)
+    }
+
+    /// Sends the following replies without splicing them
+    pub(crate) fn disable_write(&self) {
+        self.write.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns the size of the largest reply, that is spliced
+    pub(crate) fn max_read(&self) -> usize {
+        self.max_read
+    }
+
+}
+
+impl Channel {
+
+    /// Mounts a file system at `mount_point` and returns the channel of its connection.
+    pub(crate) fn mount(mount_point: &CStr, options: &[&OsStr], splice: Splice)
+        -> io::Result<Self> {
+        let mut args = vec![CString::new("strato")?];
+        for option in options {
+            args.push(CString::new(option.as_bytes())?);
+        }
+        let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+
+        let fuse_args = fuse_args {
+            argc: argv.len() as c_int,
+            argv: argv.as_ptr(),
+            allocated: 0,
+        };
+
+        let fd = unsafe { fuse_mount_compat25(mount_point.as_ptr(), &fuse_args) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Channel { fd, splice: Arc::new(splice) })
+    }
+
+    /// Opens a further channel to the connection of this channel.
+    /// Requests read from it are processed independently from the requests of this channel.
+    pub(crate) fn clone_queue(&self) -> io::Result<Self> {
+        let path = b"/dev/fuse\0";
+        let fd = unsafe {
+            libc::open(path.as_ptr() as *const c_char, libc::O_RDWR | libc::O_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Closes the descriptor, if attaching it fails
+        let channel = Channel { fd, splice: self.splice.clone() };
+
+        let mut master = self.fd as u32;
+        if unsafe { libc::ioctl(fd, FUSE_DEV_IOC_CLONE as _, &mut master) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(channel)
+    }
+
+    /// Reads the next request into the free capacity of `buffer`. It must be able to hold
+    /// the largest request, the kernel sends.
+    /// Returns false, once the file system was unmounted.
+    pub(crate) fn receive(&self, buffer: &mut BytesMut) -> io::Result<bool> {
+        loop {
+            let read = unsafe {
+                let free = buffer.bytes_mut();
+                libc::read(self.fd, free.as_mut_ptr() as *mut c_void, free.len())
+            };
+
+            if read >= 0 {
+                unsafe { buffer.advance_mut(read as usize) };
+                return Ok(true);
+            }
+
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                // The request was interrupted before it was read, or there was none yet
+                Some(libc::ENOENT) | Some(libc::EINTR) | Some(libc::EAGAIN) => continue,
+                // The connection ended
+                Some(libc::ENODEV) => return Ok(false),
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /// Writes a reply, that consists of the header and the two parts of the body, with a single
+    /// `writev`. The kernel expects a reply in one write.
+    pub(crate) fn send(&self, parts: [&[u8]; 3]) -> io::Result<()> {
+        let iovec = |part: &[u8]| libc::iovec {
+            iov_base: part.as_ptr() as *mut c_void,
+            iov_len: part.len(),
+        };
+        let iovecs = [iovec(parts[0]), iovec(parts[1]), iovec(parts[2])];
+
+        let written = unsafe {
+            libc::writev(self.fd, iovecs.as_ptr(), iovecs.len() as c_int)
+        };
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns, how replies are spliced into the channel
+    pub(crate) fn splice(&self) -> &Splice {
+        &self.splice
+    }
+
+    /// Splices a reply, that was prepared in a pipe, into the channel
+    pub(crate) fn send_spliced(&self, reply: SplicedReply) -> io::Result<()> {
+        reply.send(self.fd, self.splice.move_pages)
+    }
+
+}
+
+#[cfg(test)]
+impl Channel {
+
+    /// Returns two connected channels, each of which receives, what is sent into the other one
+    pub(crate) fn pair() -> io::Result<(Channel, Channel)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_DGRAM, 0, fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let channel = |fd| Channel { fd, splice: Arc::new(Splice::new(false, false, 0)) };
+        Ok((channel(fds[0]), channel(fds[1])))
+    }
+
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Unmounts the file system at `mount_point`. This ends the connection of all its channels.
+pub(crate) fn unmount(mount_point: &CStr) {
+    unsafe { fuse_unmount_compat22(mount_point.as_ptr()) };
+}
+
+/// Converts a path into the representation used by libfuse
+pub(crate) fn to_c_path(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
@@ -62,7 +62,8 @@ impl Decoder for FuseRequestDecoder {
                 req!(header, body)
             }
             FUSE_INTERRUPT => {
-                return Err(Error::new(Other, "Interrupting is not implemented"));
+                let body = Interrupt(fetch(src));
+                req!(header, body)
             }
             FUSE_LOOKUP => {
                 let body = Lookup(fetch_str(src));
@@ -72,6 +73,12 @@ impl Decoder for FuseRequestDecoder {
                 let body = Forget(fetch(src));
                 req!(header, body)
             }
+            FUSE_BATCH_FORGET => {
+                let arg: fuse_batch_forget_in = fetch(src);
+                let nodes = (0..arg.count).map(|_| fetch(src)).collect();
+                let body = BatchForget(nodes);
+                req!(header, body)
+            }
             FUSE_GETATTR => {
                 let body = GetAttr();
                 req!(header, body)
@@ -85,11 +92,11 @@ impl Decoder for FuseRequestDecoder {
                 req!(header, body)
             }
             FUSE_MKNOD => {
-                let body = MkNod(fetch(src));
+                let body = MkNod(fetch(src), fetch_str(src));
                 req!(header, body)
             }
             FUSE_MKDIR => {
-                let body = MkDir(fetch(src));
+                let body = MkDir(fetch(src), fetch_str(src));
                 req!(header, body)
             }
             FUSE_UNLINK => {
@@ -121,7 +128,10 @@ impl Decoder for FuseRequestDecoder {
                 req!(header, body)
             }
             FUSE_WRITE => {
-                let body = Write(fetch(src), src.to_vec());
+                let arg: fuse_write_in = fetch(src);
+                // The data stays in the buffer, it was read into
+                let data = src.split_to(arg.size as usize).freeze();
+                let body = Write(arg, data);
                 req!(header, body)
             }
             FUSE_FLUSH => {
@@ -157,11 +167,14 @@ impl Decoder for FuseRequestDecoder {
                 req!(header, body)
             }
             FUSE_SETXATTR => {
-                let body = SetXAttr(fetch(src), src.to_vec());
+                let arg: fuse_setxattr_in = fetch(src);
+                let name = fetch_str(src);
+                let value = src.split_to(arg.size as usize).to_vec();
+                let body = SetXAttr(arg, name, value);
                 req!(header, body)
             }
             FUSE_GETXATTR => {
-                let body = GetXAttr(fetch(src));
+                let body = GetXAttr(fetch(src), fetch_str(src));
                 req!(header, body)
             }
             FUSE_LISTXATTR => {
@@ -169,7 +182,7 @@ impl Decoder for FuseRequestDecoder {
                 req!(header, body)
             }
             FUSE_REMOVEXATTR => {
-                let body = RemoveXAttr(fetch(src));
+                let body = RemoveXAttr(fetch_str(src));
                 req!(header, body)
             }
             FUSE_ACCESS => {
@@ -177,7 +190,7 @@ impl Decoder for FuseRequestDecoder {
                 req!(header, body)
             }
             FUSE_CREATE => {
-                let body = Create(fetch(src));
+                let body = Create(fetch(src), fetch_str(src));
                 req!(header, body)
             }
             FUSE_GETLK => {
@@ -199,7 +212,7 @@ impl Decoder for FuseRequestDecoder {
             }
             #[cfg(target_os = "macos")]
             FUSE_EXCHANGE => {
-                let body = Exchange(fetch(src));
+                let body = Exchange(fetch(src), fetch_str(src), fetch_str(src));
                 req!(header, body)
             }
             #[cfg(target_os = "macos")]
@@ -225,8 +238,8 @@ pub fn fetch<T: Clone>(src: &mut BytesMut) -> T {
     // Return the data as the corresponding data type
     let bytes = src.split_to(len);
 
-    let dst: &T = unsafe {std::mem::transmute(bytes.as_ptr())};
-    dst.clone()
+    // The buffer is not necessarily aligned for T
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
 }
 
 pub fn fetch_str(src: &mut BytesMut) -> OsString {
@@ -238,7 +251,7 @@ pub fn fetch_str(src: &mut BytesMut) -> OsString {
     // Discard null byte
     src.advance(1);
 
-    OsString::from(std::str::from_utf8(&bytes).unwrap())
+    OsStr::from_bytes(&bytes).to_os_string()
 }
 
 pub fn fetch_path(src: &mut BytesMut) -> PathBuf {
@@ -418,6 +431,19 @@ mod tests {
         decode_and_compare(bytes, req);
     }
 
+    #[test]
+    fn interrupt() {
+        use super::*;
+        use rand::random;
+
+        let bod = fuse_interrupt_in { unique: random() };
+        let header = build_fuse_header_from_body(FUSE_INTERRUPT, &bod);
+        let bytes = serialize_fuse_request_with_body(&header, &bod);
+        let req = FuseRequest::new(header, Interrupt(bod));
+
+        decode_and_compare(bytes, req);
+    }
+
     #[test]
     fn read() {
         use super::*;
@@ -427,6 +453,9 @@ mod tests {
             fh: random(),
             offset: random(),
             size: random(),
+            read_flags: 0,
+            lock_owner: random(),
+            flags: 0,
             padding: 0
         };
         let header = build_fuse_header_from_body(FUSE_READ, &bod);
@@ -449,6 +478,9 @@ mod tests {
             fh: random(),
             offset: random(),
             size: random(),
+            read_flags: 0,
+            lock_owner: 0,
+            flags: 0,
             padding: 0
         };
         let header = build_fuse_header_from_body(FUSE_READDIR, &bod);
@@ -463,4 +495,60 @@ mod tests {
     }
 
 
+    #[test]
+    fn write() {
+        use super::*;
+        use rand::random;
+
+        let data = b"Written data".to_vec();
+        let bod = fuse_write_in {
+            fh: random(),
+            offset: random(),
+            size: data.len() as u32,
+            write_flags: 0,
+            lock_owner: 0,
+            flags: 0,
+            padding: 0,
+        };
+        let header = create_fuse_header(FUSE_WRITE,
+            size_of::<fuse_in_header>() + size_of::<fuse_write_in>() + data.len());
+
+        let mut bytes = serialize_fuse_request_with_body(&header, &bod);
+        bytes.extend_from_slice(&data);
+
+        let body = Write(bod, data.into());
+        let req = FuseRequest::new(header, body);
+
+        decode_and_compare(bytes, req);
+    }
+
+    #[test]
+    fn batch_forget() {
+        use super::*;
+        use rand::random;
+
+        let nodes = vec![
+            fuse_forget_one { nodeid: random(), nlookup: random() },
+            fuse_forget_one { nodeid: random(), nlookup: random() },
+        ];
+        let bod = fuse_batch_forget_in {
+            count: nodes.len() as u32,
+            dummy: 0,
+        };
+        let header = create_fuse_header(FUSE_BATCH_FORGET,
+            size_of::<fuse_in_header>() + size_of::<fuse_batch_forget_in>() +
+            nodes.len() * size_of::<fuse_forget_one>());
+
+        let mut bytes = serialize_fuse_request_with_body(&header, &bod);
+        for node in &nodes {
+            bytes.extend_from_slice(as_u8_slice(node));
+        }
+
+        let body = BatchForget(nodes);
+        let req = FuseRequest::new(header, body);
+
+        decode_and_compare(bytes, req);
+    }
+
+
 }
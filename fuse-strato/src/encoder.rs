@@ -28,85 +28,90 @@ impl Encoder for FuseResponseEncoder {
     type Error = Error;
 
     fn encode(&mut self, item: FuseResponse, dst: &mut BytesMut) -> Result<(), Error> {
+        let (header, parts) = encode_parts(&item)?;
 
-
-        // If the header contains an error, we will never return a body.
-        // Simply write out the header and finish
-        if item.get_header().error != 0 {
-            dst.reserve(size_of::<fuse_out_header>());
-            let mut header = item.get_header().to_owned();
-            header.error = -header.error;
-            dst.put_slice(as_u8_slice(&header));
-            return Ok(())
+        dst.reserve(header.len as usize);
+        dst.put_slice(as_u8_slice(&header));
+        for part in parts.iter() {
+            dst.put_slice(part);
         }
+        Ok(())
+    }
 
-        // Otherwise we need to check the body in order to know how to behave
-        match item.get_body() {
-
-            // These are the responses, that do not have a response body/
-            // The header is simply written out
-            Init() | Destroy() | Forget() | SetAttr() | Unlink() | RmDir() | Rename() | Flush() |
-            Release() | FSync() | ReleaseDir() | FSyncDir() | Access() | SetLock()
-            => {
-                dst.reserve(size_of::<fuse_out_header>());
-                dst.put_slice(as_u8_slice(item.get_header()));
-            },
-
-            // Empty responses which are only implemented for mac OS
-            #[cfg (target_os = "macos")]
-            SetVolumeName() | Exchange()
-            => {
-                dst.reserve(size_of::<fuse_out_header>());
-                dst.put_slice(as_u8_slice(item.get_header()));
-            },
-
-
-            Interrupt() =>
-                return Err(Error::new(Other, "Interrupting is not implemented")),
-
-            #[cfg (target_os = "macos")]
-            GetXTimes() =>
-                return Err(Error::new(Other, "GetXTimes is not implemented")),
-
-
-            // These responses respond with an Entry
-            Lookup(body) | MkNod(body) | MkDir(body) | Symlink(body)
-            => {
-                dst.reserve(size_of::<fuse_out_header>() + size_of::<fuse_entry_out>());
-                dst.put_slice(as_u8_slice(item.get_header()));
-                dst.put_slice(as_u8_slice(body));
-            },
-
-            // These responses answer with an open_out
-            Open(body) | OpenDir(body)
-            => {
-            dst.reserve(size_of::<fuse_out_header>() + size_of::<fuse_open_out>());
-            dst.put_slice(as_u8_slice(item.get_header()));
-            dst.put_slice(as_u8_slice(body));
-            },
-
-
-            // These responses have a variable length vector
-            ReadLink(data) | Read(data) | GetXAttr(data) | ListXAttr(data)
-            => {
-                dst.reserve(size_of::<fuse_out_header>() + data.len());
-                dst.put_slice(as_u8_slice(item.get_header()));
-                dst.put_slice(&data);
-            }
-
-
-            // TODO: ReadDir
-            // TODO: Create
-            // TODO: StatFs
-            _ => return Err(Error::new(Other, "This Response is unimplemented")),
-        }
+}
 
-        Ok(())
+/// Splits a response into its header and the parts of its body, that follow the header in order.
+/// The parts borrow from the response, so they can be written out with a single `writev`,
+/// without copying them into a buffer first.
+/// The length in the header always covers the whole response, regardless of the header,
+/// that was given.
+pub(crate) fn encode_parts(item: &FuseResponse) -> Result<(fuse_out_header, [&[u8]; 2]), Error> {
+
+    let mut header = item.get_header().to_owned();
+
+    // If the header contains an error, we will never return a body.
+    // Simply write out the header and finish
+    if header.error != 0 {
+        header.error = -header.error;
+        header.len = size_of::<fuse_out_header>() as u32;
+        return Ok((header, [&[], &[]]));
     }
 
+    // Otherwise we need to check the body in order to know how to behave
+    let parts: [&[u8]; 2] = match item.get_body() {
+
+        // These are the responses, that do not have a response body/
+        // The header is simply written out
+        Empty() | Destroy() | Forget() | Unlink() | RmDir() | Rename() | Flush() |
+        Release() | FSync() | ReleaseDir() | FSyncDir() | SetXAttr() | RemoveXAttr() |
+        Access() | SetLock()
+        => [&[], &[]],
+
+        // Empty responses which are only implemented for mac OS
+        #[cfg (target_os = "macos")]
+        SetVolumeName() | Exchange()
+        => [&[], &[]],
+
+
+        Interrupt() =>
+            return Err(Error::new(Other, "Interrupting is not implemented")),
+
+        #[cfg (target_os = "macos")]
+        GetXTimes() =>
+            return Err(Error::new(Other, "GetXTimes is not implemented")),
+
+
+        Init(body) => [as_u8_slice(body), &[]],
+        InitCompat(body) => [&as_u8_slice(body)[..FUSE_COMPAT_22_INIT_OUT_SIZE], &[]],
+
+        // These responses respond with an Entry
+        Lookup(body) | MkNod(body) | MkDir(body) | Symlink(body) | Link(body)
+        => [as_u8_slice(body), &[]],
+
+        // These responses respond with the attributes
+        GetAttr(body) | SetAttr(body) => [as_u8_slice(body), &[]],
+
+        // These responses answer with an open_out
+        Open(body) | OpenDir(body) => [as_u8_slice(body), &[]],
+
+        Write(body) => [as_u8_slice(body), &[]],
+        StatFS(body) => [as_u8_slice(body), &[]],
+        GetLock(body) => [as_u8_slice(body), &[]],
+        Bmap(body) => [as_u8_slice(body), &[]],
+
+        Create(entry, open) => [as_u8_slice(entry), as_u8_slice(open)],
+
+        // These responses have a variable length body
+        ReadLink(data) | Read(data) | GetXAttr(data) | ListXAttr(data) => [&data[..], &[]],
+
+        ReadDir(entries) => [entries.as_bytes(), &[]],
+    };
+
+    header.len = (size_of::<fuse_out_header>() + parts[0].len() + parts[1].len()) as u32;
+    Ok((header, parts))
 }
 
-fn as_u8_slice<T: Sized>(p: &T) -> &[u8] {
+pub(crate) fn as_u8_slice<T: Sized>(p: &T) -> &[u8] {
     use std::slice::from_raw_parts;
     unsafe {
         from_raw_parts(p as *const T as *const u8, size_of::<T>())
@@ -186,6 +191,7 @@ mod tests {
             gid: random(),
             rdev: random(),
             flags: random(),
+            blksize: random(),
         }
     }
 
@@ -260,7 +266,7 @@ mod tests {
         let mut vec = "This is data to be read".into();
 
         let header = build_fuse_header_from_vec(0, &vec);
-        let body  = Read(vec.clone());
+        let body  = Read(vec.clone().into());
 
         let response = FuseResponse::new(header.clone(), body);
 
@@ -274,4 +280,33 @@ mod tests {
     }
 
 
+    #[test]
+    fn create() {
+        use rand::random;
+        let mut buf = BytesMut::new();
+        let mut encoder = FuseResponseEncoder::new();
+
+        let entry_out = build_entry_out();
+        let open_out = fuse_open_out {
+            fh: random(),
+            open_flags: random(),
+            padding: 0,
+        };
+
+        // The length of the header is filled in by the encoder
+        let mut header = create_fuse_header(0, 0);
+        let response = FuseResponse::new(header.clone(), Create(entry_out.clone(), open_out.clone()));
+
+        encoder.encode(response, &mut buf).expect("create: Error in Encoder");
+        hexdump::hexdump(&buf);
+
+        header.len = (size_of::<fuse_out_header>() + size_of::<fuse_entry_out>() +
+            size_of::<fuse_open_out>()) as u32;
+        let mut bytes = serialize_fuse_request_with_body(&header, &entry_out);
+        bytes.append(&mut Vec::from(as_u8_slice(&open_out)));
+
+        assert_eq!(&buf, &bytes);
+    }
+
+
 }
\ No newline at end of file
@@ -75,6 +75,8 @@ pub struct FileAttr {
     pub rdev: u32,
     /// Flags (macOS only, see chflags(2))
     pub flags: u32,
+    /// Preferred block size for I/O. With 0, the block size of the file system is used.
+    pub blksize: u32,
 }
 
 /// Returns a fuse_attr from FileAttr
@@ -102,6 +104,8 @@ pub(crate) fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         gid: attr.gid,
         rdev: attr.rdev,
         flags: attr.flags,
+        blksize: attr.blksize,
+        padding: 0,
     }
 }
 
@@ -126,15 +130,38 @@ pub(crate) fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         uid: attr.uid,
         gid: attr.gid,
         rdev: attr.rdev,
+        blksize: attr.blksize,
+        padding: 0,
     }
 }
 
 
 /// Takes a `SystemTime` and returns the time since EPOCH in seconds and nanoseconds.
+/// Times before EPOCH have negative seconds, but the nanoseconds still count forward.
 pub(crate) fn system_time_decompose(st: &SystemTime) -> (i64, i32) {
-    if let Ok(dur_since_epoch) = st.duration_since(std::time::UNIX_EPOCH) {
-        (dur_since_epoch.as_secs() as i64, dur_since_epoch.subsec_nanos() as i32)
+    match st.duration_since(std::time::UNIX_EPOCH) {
+        Ok(dur_since_epoch) =>
+            (dur_since_epoch.as_secs() as i64, dur_since_epoch.subsec_nanos() as i32),
+        Err(before) => {
+            let dur_before_epoch = before.duration();
+            match dur_before_epoch.subsec_nanos() {
+                0 => (-(dur_before_epoch.as_secs() as i64), 0),
+                nanos => (-(dur_before_epoch.as_secs() as i64) - 1,
+                          1_000_000_000 - nanos as i32),
+            }
+        }
+    }
+}
+
+/// Takes the time since EPOCH in seconds and nanoseconds and returns it as a `SystemTime`.
+pub(crate) fn system_time_compose(secs: i64, nsecs: i32) -> SystemTime {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // The nanoseconds always count forward, even for times before EPOCH
+    let nanos = Duration::new(0, nsecs as u32);
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64) + nanos
     } else {
-        panic!("The system time is before UNIX EPOCH. Fix your system clock");
+        UNIX_EPOCH - Duration::from_secs(secs.wrapping_neg() as u64) + nanos
     }
 }
@@ -9,3 +9,15 @@ mod request;
 
 mod decoder;
 mod encoder;
+
+mod channel;
+mod pool;
+mod splice;
+mod reply;
+mod session;
+
+pub use crate::file::{FileType, FileAttr};
+pub use crate::reply::{ReplyEmpty, ReplyEntry, ReplyAttr, ReplyData, ReplyOpen, ReplyWrite,
+                       ReplyStatfs, ReplyCreate, ReplyLock, ReplyBmap, ReplyXattr,
+                       ReplyDirectory};
+pub use crate::session::{Session, Config, Filesystem, Request};
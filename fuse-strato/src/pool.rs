@@ -0,0 +1,96 @@
+use bytes::{Bytes, BytesMut};
+
+/// The number of buffers, a pool keeps at most. Further buffers are released, once the data
+/// in them is dropped.
+const MAX_BUFFERS: usize = 8;
+
+/// Recycles the buffers, that requests are read into.
+///
+/// The data of a write is handed to the file system as a slice of the buffer, it was read into.
+/// So a buffer can only be reused, once the file system dropped all data of the request.
+/// Until then, the pool hands out other buffers, and only allocates, if all are still in use.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    size: usize,
+    buffers: Vec<Bytes>,
+}
+
+impl BufferPool {
+
+    /// Creates a pool of buffers, that hold `size` bytes each
+    pub(crate) fn new(size: usize) -> Self {
+        BufferPool {
+            size,
+            buffers: Vec::with_capacity(MAX_BUFFERS),
+        }
+    }
+
+    /// Returns an empty buffer, that holds `size` bytes
+    pub(crate) fn get(&mut self) -> BytesMut {
+        for index in 0..self.buffers.len() {
+            // Takes the buffer out, without moving the others
+            let last = self.buffers.len() - 1;
+            self.buffers.swap(index, last);
+            let buffer = self.buffers.pop().unwrap();
+
+            match buffer.try_mut() {
+                Ok(mut buffer) => {
+                    // Moves back to the start of the allocation, without allocating
+                    buffer.clear();
+                    buffer.reserve(self.size);
+                    return buffer;
+                },
+                Err(buffer) => {
+                    self.buffers.push(buffer);
+                    self.buffers.swap(index, last);
+                }
+            }
+        }
+
+        BytesMut::with_capacity(self.size)
+    }
+
+    /// Takes a buffer back, after the request read into it was decoded
+    pub(crate) fn put(&mut self, buffer: BytesMut) {
+        if self.buffers.len() < MAX_BUFFERS {
+            self.buffers.push(buffer.freeze());
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BufMut;
+
+    #[test]
+    fn reuse() {
+        let mut pool = BufferPool::new(1024);
+
+        let mut buffer = pool.get();
+        buffer.put_slice(&[1; 512]);
+        let first = buffer.as_ptr();
+
+        // The data of the request is still in use
+        let mut request = buffer.take();
+        let data = request.split_to(256).freeze();
+        drop(request);
+        pool.put(buffer);
+
+        let other = pool.get();
+        assert_ne!(other.as_ptr(), first);
+        assert!(other.capacity() >= 1024);
+        pool.put(other);
+
+        // Once the data is dropped, the buffer is reused from its start
+        drop(data);
+        let reused = pool.get();
+        assert_eq!(reused.as_ptr(), first);
+        assert!(reused.capacity() >= 1024);
+        assert!(reused.is_empty());
+    }
+
+}
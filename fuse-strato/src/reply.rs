@@ -0,0 +1,371 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::mem::size_of;
+use std::os::unix::io::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use libc::{c_int, EIO, EPERM};
+
+use fuse_sys::abi::*;
+
+use crate::channel::Channel;
+use crate::encoder::{self, as_u8_slice};
+use crate::file::{FileAttr, FileType};
+use crate::response::{self, FuseResponse, FuseResponseBody, DirReply};
+use crate::splice::{self, SplicedReply};
+
+// The replies are modeled after the replies of Andreas Neuhaus' rust-fuse, so that a file
+// system can move between both. Every reply is sent on the channel, the request was read from.
+
+
+/// The request, a reply answers. It replies with `EIO`, if it is dropped without replying.
+#[derive(Debug)]
+struct Reply {
+    unique: u64,
+    channel: Option<Arc<Channel>>,
+}
+
+impl Reply {
+
+    fn send(&mut self, error: c_int, body: FuseResponseBody) {
+        let channel = match self.channel.take() {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let header = fuse_out_header {
+            len: 0,
+            error,
+            unique: self.unique,
+        };
+
+        let response = FuseResponse::new(header, body);
+        let (header, body) = match encoder::encode_parts(&response) {
+            Ok(parts) => parts,
+            Err(error) => {
+                error!("Failed to encode the reply to request {}: {}", self.unique, error);
+                return;
+            }
+        };
+
+        // Fails, if the request was interrupted in the meantime
+        if let Err(error) = channel.send([as_u8_slice(&header), body[0], body[1]]) {
+            warn!("Failed to send the reply to request {}: {}", self.unique, error);
+        }
+    }
+
+    /// Splices `len` bytes of `fd` at `offset` into the channel, if it allows splicing.
+    /// Returns false, if the reply was not sent.
+    fn splice(&mut self, fd: RawFd, offset: i64, len: usize) -> bool {
+        let channel = match self.channel {
+            Some(ref channel) if channel.splice().write() => channel.clone(),
+            _ => return false,
+        };
+
+        // Nothing was sent yet, if preparing the reply fails
+        let max_read = channel.splice().max_read();
+        let reply = match SplicedReply::new(self.unique, fd, offset, len, max_read) {
+            Ok(reply) => reply,
+            Err(ref error) if error.raw_os_error() == Some(EPERM) => {
+                // Unprivileged processes may not grow the pipes far enough
+                warn!("Failed to create the pipes for splicing, writing replies instead: {}",
+                      error);
+                channel.splice().disable_write();
+                return false;
+            },
+            Err(error) => {
+                debug!("Failed to splice the reply to request {}: {}", self.unique, error);
+                return false;
+            }
+        };
+
+        self.channel = None;
+        if let Err(error) = channel.send_spliced(reply) {
+            warn!("Failed to send the reply to request {}: {}", self.unique, error);
+        }
+        true
+    }
+
+    fn error(mut self, error: c_int) {
+        self.send(error, FuseResponseBody::Empty());
+    }
+
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if self.channel.is_some() {
+            warn!("Request {} was not answered, replying EIO", self.unique);
+            self.send(EIO, FuseResponseBody::Empty());
+        }
+    }
+}
+
+
+macro_rules! reply {
+    ($(#[$doc:meta])* $name:ident) => [
+        $(#[$doc])*
+        #[derive(Debug)]
+        pub struct $name {
+            reply: Reply,
+        }
+
+        impl $name {
+            pub(crate) fn new(unique: u64, channel: Arc<Channel>) -> Self {
+                $name { reply: Reply { unique, channel: Some(channel) } }
+            }
+
+            /// Replies with an error code, e.g. `ENOENT`
+            pub fn error(self, error: c_int) {
+                self.reply.error(error);
+            }
+        }
+    ];
+    ($(#[$doc:meta])* $name:ident, $body:ty) => [
+        $(#[$doc])*
+        #[derive(Debug)]
+        pub struct $name {
+            reply: Reply,
+            // Wraps the reply into the body of the operation, that is answered
+            op: fn($body) -> FuseResponseBody,
+        }
+
+        impl $name {
+            pub(crate) fn new(unique: u64, channel: Arc<Channel>,
+                              op: fn($body) -> FuseResponseBody) -> Self {
+                $name { reply: Reply { unique, channel: Some(channel) }, op }
+            }
+
+            /// Replies with an error code, e.g. `ENOENT`
+            pub fn error(self, error: c_int) {
+                self.reply.error(error);
+            }
+
+            fn send(mut self, body: $body) {
+                let body = (self.op)(body);
+                self.reply.send(0, body);
+            }
+        }
+    ];
+}
+
+reply!(
+    /// The reply to operations, that do not return data
+    ReplyEmpty
+);
+
+impl ReplyEmpty {
+    /// Replies, that the operation succeeded
+    pub fn ok(mut self) {
+        self.reply.send(0, FuseResponseBody::Empty());
+    }
+}
+
+reply!(
+    /// The reply to `init`, which is handled by the session
+    ReplyInit, fuse_init_out
+);
+
+impl ReplyInit {
+    pub(crate) fn init(self, max_readahead: u32, flags: u32, max_write: u32, max_pages: u16) {
+        self.send(response::init(max_readahead, flags, max_write, max_pages));
+    }
+}
+
+reply!(
+    /// The reply to `lookup`, `mknod`, `mkdir`, `symlink` and `link`
+    ReplyEntry, fuse_entry_out
+);
+
+impl ReplyEntry {
+    /// Replies with the entry and its attributes, which the kernel caches for `ttl`
+    pub fn entry(self, ttl: &Duration, attr: &FileAttr, generation: u64) {
+        self.send(response::entry(ttl, attr, generation));
+    }
+}
+
+reply!(
+    /// The reply to `getattr` and `setattr`
+    ReplyAttr, fuse_attr_out
+);
+
+impl ReplyAttr {
+    /// Replies with the attributes, which the kernel caches for `ttl`
+    pub fn attr(self, ttl: &Duration, attr: &FileAttr) {
+        self.send(response::attr(ttl, attr));
+    }
+}
+
+reply!(
+    /// The reply to `read` and `readlink`
+    ReplyData, Bytes
+);
+
+impl ReplyData {
+    /// Replies with the data. The data is written out as it is, so passing an owned buffer,
+    /// e.g. a `Vec<u8>`, does not copy it.
+    pub fn data<T: Into<Bytes>>(self, data: T) {
+        self.send(data.into());
+    }
+
+    /// Replies with up to `len` bytes of the file `fd`, starting at `offset`. Fewer bytes are
+    /// sent at the end of the file. The data is spliced from the file into the kernel, if the
+    /// session allows it, and read into a buffer otherwise. `fd` is not closed.
+    pub fn splice(mut self, fd: RawFd, offset: i64, len: u32) {
+        if self.reply.splice(fd, offset, len as usize) {
+            return;
+        }
+        match splice::read_at(fd, offset, len as usize) {
+            Ok(data) => self.data(data),
+            Err(error) => self.error(error.raw_os_error().unwrap_or(EIO)),
+        }
+    }
+}
+
+reply!(
+    /// The reply to `open` and `opendir`
+    ReplyOpen, fuse_open_out
+);
+
+impl ReplyOpen {
+    /// Replies with the handle of the opened file and the flags for the kernel, e.g.
+    /// `FOPEN_DIRECT_IO`
+    pub fn opened(self, fh: u64, flags: u32) {
+        self.send(response::open(fh, flags));
+    }
+}
+
+reply!(
+    /// The reply to `write`
+    ReplyWrite, fuse_write_out
+);
+
+impl ReplyWrite {
+    /// Replies with the number of bytes written
+    pub fn written(self, size: u32) {
+        self.send(response::write(size));
+    }
+}
+
+reply!(
+    /// The reply to `statfs`
+    ReplyStatfs, fuse_statfs_out
+);
+
+impl ReplyStatfs {
+    /// Replies with the statistics of the file system
+    // Each field of `fuse_kstatfs` is an argument, like in the other replies
+    #[allow(clippy::too_many_arguments)]
+    pub fn statfs(self, blocks: u64, bfree: u64, bavail: u64, files: u64, ffree: u64,
+                  bsize: u32, namelen: u32, frsize: u32) {
+        self.send(response::statfs(blocks, bfree, bavail, files, ffree, bsize, namelen, frsize));
+    }
+}
+
+reply!(
+    /// The reply to `create`
+    ReplyCreate, (fuse_entry_out, fuse_open_out)
+);
+
+impl ReplyCreate {
+    /// Replies with the created entry, which the kernel caches for `ttl`, and the handle of
+    /// the opened file
+    pub fn created(self, ttl: &Duration, attr: &FileAttr, generation: u64, fh: u64, flags: u32) {
+        self.send(response::create(ttl, attr, generation, fh, flags));
+    }
+}
+
+reply!(
+    /// The reply to `getlk`
+    ReplyLock, fuse_lk_out
+);
+
+impl ReplyLock {
+    /// Replies with the lock, that conflicts with the requested one
+    pub fn locked(self, start: u64, end: u64, typ: u32, pid: u32) {
+        self.send(response::lock(start, end, typ, pid));
+    }
+}
+
+reply!(
+    /// The reply to `bmap`
+    ReplyBmap, fuse_bmap_out
+);
+
+impl ReplyBmap {
+    /// Replies with the index of the block on the device
+    pub fn bmap(self, block: u64) {
+        self.send(response::bmap(block));
+    }
+}
+
+reply!(
+    /// The reply to `getxattr` and `listxattr`
+    ReplyXattr, Bytes
+);
+
+impl ReplyXattr {
+    /// Replies with the size of the value, if the request asked for the size only
+    pub fn size(self, size: u32) {
+        self.send(Bytes::from(as_u8_slice(&response::xattr_size(size))));
+    }
+
+    /// Replies with the value
+    pub fn data<T: Into<Bytes>>(self, data: T) {
+        self.send(data.into());
+    }
+}
+
+
+/// The reply to `readdir`. Entries are added, until the buffer of the kernel is full.
+#[derive(Debug)]
+pub struct ReplyDirectory {
+    reply: Reply,
+    entries: DirReply,
+    size: usize,
+}
+
+impl ReplyDirectory {
+
+    pub(crate) fn new(unique: u64, channel: Arc<Channel>, size: usize) -> Self {
+        ReplyDirectory {
+            reply: Reply { unique, channel: Some(channel) },
+            entries: DirReply::new(),
+            size,
+        }
+    }
+
+    /// Adds an entry, that is followed by the position `offset`.
+    /// Returns true, if the buffer is full and the entry was not added.
+    pub fn add<T: AsRef<OsStr>>(&mut self, ino: u64, offset: i64, kind: FileType, name: T)
+        -> bool {
+        // Entries are padded to 8 bytes
+        let name = name.as_ref();
+        let len = (size_of::<fuse_dirent>() + name.len() + 7) & !7;
+        if self.entries.len() + len > self.size {
+            return true;
+        }
+
+        self.entries.entry(ino, offset, kind, Path::new(name));
+        false
+    }
+
+    /// Returns the size of the buffer, the kernel provided for the entries
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Replies with the added entries
+    pub fn ok(mut self) {
+        let entries = std::mem::replace(&mut self.entries, DirReply::new());
+        self.reply.send(0, FuseResponseBody::ReadDir(entries));
+    }
+
+    /// Replies with an error code, e.g. `ENOENT`
+    pub fn error(self, error: c_int) {
+        self.reply.error(error);
+    }
+
+}
@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use std::ffi::OsString;
 
+use bytes::Bytes;
+
 use fuse_sys::abi::*;
 use fuse_sys::abi::consts::*;
 use fuse_sys::abi::fuse_opcode::*;
@@ -16,6 +18,14 @@ impl FuseRequest {
     pub(crate) fn new(header: fuse_in_header, body: FuseRequestBody) -> Self {
         FuseRequest {header, body}
     }
+
+    pub(crate) fn get_header(&self) -> &fuse_in_header {
+        &self.header
+    }
+
+    pub(crate) fn into_parts(self) -> (fuse_in_header, FuseRequestBody) {
+        (self.header, self.body)
+    }
 }
 
 
@@ -23,15 +33,15 @@ impl FuseRequest {
 pub(crate) enum FuseRequestBody {
     Init(fuse_init_in),
     Destroy(),
-    /// Unimplemented
-    Interrupt(),
+    Interrupt(fuse_interrupt_in),
     Lookup(OsString),
     Forget(fuse_forget_in),
+    BatchForget(Vec<fuse_forget_one>),
     GetAttr(),
     SetAttr(fuse_setattr_in),
     ReadLink(),
-    MkNod(fuse_mknod_in),
-    MkDir(OsString),
+    MkNod(fuse_mknod_in, OsString),
+    MkDir(fuse_mkdir_in, OsString),
     Unlink(OsString),
     RmDir(OsString),
     Symlink(OsString, PathBuf),
@@ -39,7 +49,7 @@ pub(crate) enum FuseRequestBody {
     Link(fuse_link_in, OsString),
     Open(fuse_open_in),
     Read(fuse_read_in),
-    Write(fuse_write_in, Vec<u8>),
+    Write(fuse_write_in, Bytes),
     Flush(fuse_flush_in),
     Release(fuse_release_in),
     FSync(fuse_fsync_in),
@@ -48,12 +58,12 @@ pub(crate) enum FuseRequestBody {
     ReleaseDir(fuse_release_in),
     FSyncDir(fuse_fsync_in),
     StatFS(),
-    SetXAttr(fuse_setxattr_in, Vec<u8>),
-    GetXAttr(fuse_getxattr_in),
+    SetXAttr(fuse_setxattr_in, OsString, Vec<u8>),
+    GetXAttr(fuse_getxattr_in, OsString),
     ListXAttr(fuse_getxattr_in),
     RemoveXAttr(OsString),
     Access(fuse_access_in),
-    Create(fuse_open_in),
+    Create(fuse_create_in, OsString),
     GetLock(fuse_lk_in),
     SetLock(fuse_lk_in),
     Bmap(fuse_bmap_in),
@@ -1,9 +1,11 @@
-use std::time::SystemTime;
+use std::time::{SystemTime, Duration};
 
 use std::path::PathBuf;
 use std::ffi::{OsStr, OsString};
 use std::os::unix::ffi::OsStrExt;
 
+use bytes::Bytes;
+
 use fuse_sys::abi::*;
 use fuse_sys::abi::consts::*;
 use fuse_sys::abi::fuse_opcode::*;
@@ -13,12 +15,12 @@ use crate::file::{system_time_decompose, fuse_attr_from_attr};
 use crate::file::mode_from_kind_and_perm;
 
 // For ReplyEmpty, as we can use ()
-// For ReplyData as we can use Vec<u8>
-// For XAttr we can use Vec<u8>
+// For ReplyData as we can use Bytes
+// For XAttr we can use Bytes
 
-pub fn entry(ttl: &SystemTime, attr: &FileAttr, generation: u64) -> fuse_entry_out {
+pub fn entry(ttl: &Duration, attr: &FileAttr, generation: u64) -> fuse_entry_out {
 
-    let (valid_s, valid_n) = system_time_decompose(ttl);
+    let (valid_s, valid_n) = duration_decompose(ttl);
 
     fuse_entry_out {
         nodeid: attr.ino,
@@ -32,8 +34,8 @@ pub fn entry(ttl: &SystemTime, attr: &FileAttr, generation: u64) -> fuse_entry_o
 }
 
 
-pub fn attr(ttl: &SystemTime, attr: &FileAttr) -> fuse_attr_out {
-    let (valid_s, valid_n) = system_time_decompose(ttl);
+pub fn attr(ttl: &Duration, attr: &FileAttr) -> fuse_attr_out {
+    let (valid_s, valid_n) = duration_decompose(ttl);
 
     fuse_attr_out {
         attr_valid: valid_s,
@@ -58,6 +60,25 @@ pub fn reply_x_times(bkuptime: &SystemTime, crtime: &SystemTime) -> fuse_getxtim
 }
 
 
+pub fn init(max_readahead: u32, flags: u32, max_write: u32, max_pages: u16) -> fuse_init_out {
+    fuse_init_out {
+        major: FUSE_KERNEL_VERSION,
+        minor: FUSE_KERNEL_MINOR_VERSION,
+        max_readahead,
+        flags,
+        // The kernel keeps its defaults
+        max_background: 0,
+        congestion_threshold: 0,
+        max_write,
+        // Times are kept in nanoseconds
+        time_gran: 1,
+        max_pages,
+        padding: 0,
+        unused: [0; 8],
+    }
+}
+
+
 pub fn open(fh: u64, flags: u32) -> fuse_open_out {
     fuse_open_out {
         fh,
@@ -75,6 +96,7 @@ pub fn write(size: u32) -> fuse_write_out {
 }
 
 
+#[allow(clippy::too_many_arguments)]
 pub fn statfs(blocks: u64, bfree: u64, bavail: u64, files: u64, ffree: u64,
               bsize: u32, namelen: u32, frsize: u32) -> fuse_statfs_out {
     fuse_statfs_out {
@@ -94,10 +116,10 @@ pub fn statfs(blocks: u64, bfree: u64, bavail: u64, files: u64, ffree: u64,
 }
 
 
-pub fn create(ttl: &SystemTime, attr: &FileAttr, generation: u64,
+pub fn create(ttl: &Duration, attr: &FileAttr, generation: u64,
               fh: u64, flags: u32) -> (fuse_entry_out, fuse_open_out) {
 
-    let (valid_s, valid_n) = system_time_decompose(ttl);
+    let (valid_s, valid_n) = duration_decompose(ttl);
 
     (fuse_entry_out {
         nodeid: attr.ino,
@@ -135,6 +157,20 @@ pub fn bmap(block: u64) -> fuse_bmap_out {
 }
 
 
+pub fn xattr_size(size: u32) -> fuse_getxattr_out {
+    fuse_getxattr_out {
+        size,
+        padding: 0,
+    }
+}
+
+
+/// Takes a relative `Duration` and returns it in seconds and nanoseconds.
+fn duration_decompose(duration: &Duration) -> (i64, i32) {
+    (duration.as_secs() as i64, duration.subsec_nanos() as i32)
+}
+
+
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DirReply(Vec<u8>);
@@ -174,6 +210,15 @@ impl DirReply {
 
     }
 
+    /// Returns the number of bytes, the entries take up in the reply
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
     pub(crate) fn to_vec(self) -> Vec<u8> {
         self.0
     }
@@ -206,15 +251,19 @@ impl FuseResponse {
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) enum FuseResponseBody {
-    Init(),
+    /// The body of a reply, that consists of the header only, e.g. to report an error
+    Empty(),
+    Init(fuse_init_out),
+    /// The reply to init, as kernels before ABI 7.23 expect it
+    InitCompat(fuse_init_out),
     Destroy(),
     /// Unimplemented
     Interrupt(),
     Lookup(fuse_entry_out),
     Forget(),
     GetAttr(fuse_attr_out),
-    SetAttr(),
-    ReadLink(Vec<u8>),
+    SetAttr(fuse_attr_out),
+    ReadLink(Bytes),
     MkNod(fuse_entry_out),
     MkDir(fuse_entry_out),
     Unlink(),
@@ -223,7 +272,7 @@ pub(crate) enum FuseResponseBody {
     Rename(),
     Link(fuse_entry_out),
     Open(fuse_open_out),
-    Read(Vec<u8>),
+    Read(Bytes),
     Write(fuse_write_out),
     Flush(),
     Release(),
@@ -234,8 +283,8 @@ pub(crate) enum FuseResponseBody {
     FSyncDir(),
     StatFS(fuse_statfs_out),
     SetXAttr(),
-    GetXAttr(Vec<u8>),
-    ListXAttr(Vec<u8>),
+    GetXAttr(Bytes),
+    ListXAttr(Bytes),
     RemoveXAttr(),
     Access(),
     Create(fuse_entry_out, fuse_open_out),
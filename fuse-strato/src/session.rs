@@ -0,0 +1,706 @@
+use std::io;
+use std::ffi::{CString, OsStr, OsString};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+use std::mem::size_of;
+
+use bytes::{Bytes, BytesMut};
+use libc::{c_int, ENOSYS, EPROTO, EINVAL};
+
+use tokio::codec::Decoder;
+
+use fuse_sys::abi::*;
+use fuse_sys::abi::consts::*;
+
+use crate::channel::{self, Channel, Splice};
+use crate::decoder::FuseRequestDecoder;
+use crate::pool::BufferPool;
+use crate::splice;
+use crate::file::system_time_compose;
+use crate::request::FuseRequestBody::*;
+use crate::response::{self, FuseResponseBody};
+use crate::reply::*;
+
+/// The room for the header and the arguments of a request, besides the data of a write
+const HEADER_SIZE: usize = 4096;
+
+/// The size of the pages, in which the kernel limits the size of requests
+const PAGE_SIZE: u64 = 4096;
+
+/// The data of smaller writes is copied out of the buffer, the request was read into. A file
+/// system, that keeps it, does not hold on to the whole buffer then.
+const MAX_COPIED_WRITE: usize = 64 * 1024;
+
+/// The capabilities, that are accepted, if the kernel offers them
+const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_POSIX_LOCKS | FUSE_BIG_WRITES | FUSE_FLOCK_LOCKS
+    | FUSE_MAX_PAGES;
+
+
+/// The settings of a session
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The number of threads, that handle requests
+    pub workers: usize,
+    /// Replies with the data of a file are spliced into the kernel, without copying the data
+    /// into userspace. See `ReplyData::splice`. Reads are limited to the data, that fits into
+    /// a pipe.
+    pub splice_write: bool,
+    /// The pages of spliced replies are moved into the kernel instead of copied
+    pub splice_move: bool,
+    /// The largest amount of data, the kernel sends with a single write
+    pub max_write: u32,
+    /// The largest amount of data, the kernel requests with a single read
+    pub max_read: u32,
+    /// The largest amount of data, the kernel reads ahead of sequential reads. The kernel
+    /// does not read ahead further than its own limit.
+    pub max_readahead: u32,
+}
+
+impl Config {
+
+    /// Returns the number of pages, that the largest request spans. Kernels since Linux 4.20
+    /// split larger requests than 32 pages only, if this is negotiated, and take up to 256.
+    fn max_pages(&self) -> u16 {
+        let size = u64::from(self.max_write.max(self.max_read));
+        size.div_ceil(PAGE_SIZE).clamp(1, u64::from(u16::MAX)) as u16
+    }
+
+    /// Returns the size of the buffers, the requests are read into
+    fn buffer_size(&self) -> usize {
+        (self.max_write as usize + HEADER_SIZE).max(FUSE_MIN_READ_BUFFER)
+    }
+
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            workers: 1,
+            splice_write: false,
+            splice_move: false,
+            max_write: 1024 * 1024,
+            max_read: 1024 * 1024,
+            max_readahead: 1024 * 1024,
+        }
+    }
+}
+
+
+/// The sender of a request
+#[derive(Debug, Clone)]
+pub struct Request {
+    header: fuse_in_header,
+}
+
+impl Request {
+
+    /// Returns the id, the kernel assigned to the request
+    pub fn unique(&self) -> u64 {
+        self.header.unique
+    }
+
+    /// Returns the user id of the calling process
+    pub fn uid(&self) -> u32 {
+        self.header.uid
+    }
+
+    /// Returns the group id of the calling process
+    pub fn gid(&self) -> u32 {
+        self.header.gid
+    }
+
+    /// Returns the process id of the calling process
+    pub fn pid(&self) -> u32 {
+        self.header.pid
+    }
+
+}
+
+
+/// The operations of a file system, as the kernel requests them.
+///
+/// The requests are read by several worker threads at the same time, so all operations take
+/// `&self`. Every operation is answered through its reply, which may be moved to another thread
+/// and answered later. Operations, that are not implemented, reply with `ENOSYS`.
+// The operations take the arguments of the kernel's requests one by one
+#[allow(unused_variables, clippy::too_many_arguments)]
+pub trait Filesystem: Send + Sync + 'static {
+
+    /// Called before any other operation, once the connection to the kernel is established
+    fn init(&self, req: &Request) -> Result<(), c_int> {
+        Ok(())
+    }
+
+    /// Called, when the file system is unmounted
+    fn destroy(&self, req: &Request) {}
+
+    fn lookup(&self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        reply.error(ENOSYS);
+    }
+
+    /// The calling process of request `unique` received a signal, so the kernel asks to abort
+    /// it. An aborted request is answered with `EINTR`, the interrupt itself is not answered.
+    fn interrupt(&self, req: &Request, unique: u64) {}
+
+    /// The kernel forgets `nlookup` lookups of the node. This is not answered.
+    fn forget(&self, req: &Request, ino: u64, nlookup: u64) {}
+
+    fn getattr(&self, req: &Request, ino: u64, reply: ReplyAttr) {
+        reply.error(ENOSYS);
+    }
+
+    fn setattr(&self, req: &Request, ino: u64, mode: Option<u32>, uid: Option<u32>,
+               gid: Option<u32>, size: Option<u64>, atime: Option<SystemTime>,
+               mtime: Option<SystemTime>, fh: Option<u64>, reply: ReplyAttr) {
+        reply.error(ENOSYS);
+    }
+
+    fn readlink(&self, req: &Request, ino: u64, reply: ReplyData) {
+        reply.error(ENOSYS);
+    }
+
+    fn mknod(&self, req: &Request, parent: u64, name: &OsStr, mode: u32, rdev: u32,
+             reply: ReplyEntry) {
+        reply.error(ENOSYS);
+    }
+
+    fn mkdir(&self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        reply.error(ENOSYS);
+    }
+
+    fn unlink(&self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn rmdir(&self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn symlink(&self, req: &Request, parent: u64, name: &OsStr, link: &Path,
+               reply: ReplyEntry) {
+        reply.error(ENOSYS);
+    }
+
+    fn rename(&self, req: &Request, parent: u64, name: &OsStr, newparent: u64,
+              newname: &OsStr, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn link(&self, req: &Request, ino: u64, newparent: u64, newname: &OsStr,
+            reply: ReplyEntry) {
+        reply.error(ENOSYS);
+    }
+
+    fn open(&self, req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        reply.error(ENOSYS);
+    }
+
+    fn read(&self, req: &Request, ino: u64, fh: u64, offset: i64, size: u32,
+            reply: ReplyData) {
+        reply.error(ENOSYS);
+    }
+
+    /// Data of 64KB and more is a slice of the buffer, the request was read into. The buffer
+    /// is only reused, once the data is dropped, so data, that is kept, should be copied.
+    fn write(&self, req: &Request, ino: u64, fh: u64, offset: i64, data: Bytes, flags: u32,
+             reply: ReplyWrite) {
+        reply.error(ENOSYS);
+    }
+
+    fn flush(&self, req: &Request, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn release(&self, req: &Request, ino: u64, fh: u64, flags: u32, lock_owner: u64,
+               flush: bool, reply: ReplyEmpty) {
+        reply.ok();
+    }
+
+    fn fsync(&self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn opendir(&self, req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        reply.error(ENOSYS);
+    }
+
+    fn readdir(&self, req: &Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+        reply.error(ENOSYS);
+    }
+
+    fn releasedir(&self, req: &Request, ino: u64, fh: u64, flags: u32, reply: ReplyEmpty) {
+        reply.ok();
+    }
+
+    fn fsyncdir(&self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn statfs(&self, req: &Request, ino: u64, reply: ReplyStatfs) {
+        reply.error(ENOSYS);
+    }
+
+    fn setxattr(&self, req: &Request, ino: u64, name: &OsStr, value: &[u8], flags: u32,
+                reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn getxattr(&self, req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        reply.error(ENOSYS);
+    }
+
+    fn listxattr(&self, req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        reply.error(ENOSYS);
+    }
+
+    fn removexattr(&self, req: &Request, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn access(&self, req: &Request, ino: u64, mask: u32, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn create(&self, req: &Request, parent: u64, name: &OsStr, mode: u32, flags: u32,
+              reply: ReplyCreate) {
+        reply.error(ENOSYS);
+    }
+
+    fn getlk(&self, req: &Request, ino: u64, fh: u64, lock_owner: u64, start: u64, end: u64,
+             typ: u32, pid: u32, reply: ReplyLock) {
+        reply.error(ENOSYS);
+    }
+
+    fn setlk(&self, req: &Request, ino: u64, fh: u64, lock_owner: u64, start: u64, end: u64,
+             typ: u32, pid: u32, sleep: bool, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    /// Acquires or releases a `flock(2)` lock, which covers the whole file and belongs to the
+    /// open file `fh`. These are sent instead of `setlk`, if the process locks with `flock`.
+    fn flock(&self, req: &Request, ino: u64, fh: u64, typ: u32, pid: u32, sleep: bool,
+             reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    fn bmap(&self, req: &Request, ino: u64, blocksize: u32, idx: u64, reply: ReplyBmap) {
+        reply.error(ENOSYS);
+    }
+
+}
+
+
+/// A mounted file system, whose requests are handled by a pool of worker threads.
+///
+/// Every worker reads from a channel of its own, that was cloned from the channel of the mount,
+/// so the workers neither contend for reading requests nor for sending replies.
+/// The file system is unmounted, when the session is dropped.
+#[derive(Debug)]
+pub struct Session {
+    mount_point: CString,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Session {
+
+    /// Mounts `filesystem` at `mount_point` and starts the worker threads, that handle its
+    /// requests.
+    pub fn spawn<FS: Filesystem>(filesystem: FS, mount_point: &Path, options: &[&OsStr],
+                                 config: &Config) -> io::Result<Self> {
+        let config = &Session::limit_reads(config);
+        let mount_point = channel::to_c_path(mount_point)?;
+        let splice = Splice::new(config.splice_write, config.splice_move,
+                                 config.max_read as usize);
+        // The kernel takes the size of reads as an option of the mount
+        let max_read = OsString::from(format!("max_read={}", config.max_read));
+        let mut options = options.to_vec();
+        options.push(OsStr::new("-o"));
+        options.push(&max_read);
+
+        let master = Arc::new(Channel::mount(&mount_point, &options, splice)?);
+        let filesystem = Arc::new(filesystem);
+
+        let mut session = Session {
+            mount_point,
+            workers: Vec::new(),
+        };
+
+        for worker in 0..config.workers.max(1) {
+            // The first worker reads from the channel of the mount itself. Kernels, that can
+            // not clone channels, let all workers share it.
+            let channel = match worker {
+                0 => master.clone(),
+                _ => match master.clone_queue() {
+                    Ok(channel) => Arc::new(channel),
+                    Err(error) => {
+                        warn!("Failed to clone the FUSE channel, sharing it instead: {}", error);
+                        master.clone()
+                    }
+                },
+            };
+
+            let filesystem = filesystem.clone();
+            let config = config.clone();
+            let thread = thread::Builder::new()
+                .name(format!("fuse-worker-{}", worker))
+                .spawn(move || Session::work(&*filesystem, channel, &config))?;
+            session.workers.push(thread);
+        }
+
+        Ok(session)
+    }
+
+    /// Limits the reads to the data, that fits into the pipes of a spliced reply. The kernel
+    /// splits larger reads, instead of the replies failing to be spliced.
+    fn limit_reads(config: &Config) -> Config {
+        let mut config = config.clone();
+        if !config.splice_write {
+            return config;
+        }
+        match splice::max_len() {
+            Ok(max_len) if config.max_read as usize > max_len => {
+                info!("Limiting reads to {} bytes, which fit into a pipe", max_len);
+                config.max_read = max_len as u32;
+            },
+            Ok(_) => {},
+            Err(error) => {
+                warn!("Failed to size the pipes for splicing, writing replies instead: {}",
+                      error);
+                config.splice_write = false;
+            }
+        }
+        config
+    }
+
+    /// Handles requests read from `channel`, until the connection ends
+    fn work<FS: Filesystem>(filesystem: &FS, channel: Arc<Channel>, config: &Config) {
+        let mut decoder = FuseRequestDecoder::new();
+        let mut pool = BufferPool::new(config.buffer_size());
+
+        loop {
+            let mut buffer = pool.get();
+
+            match channel.receive(&mut buffer) {
+                Ok(true) => {},
+                Ok(false) => break,
+                Err(error) => {
+                    error!("Failed to read from the FUSE channel: {}", error);
+                    break;
+                }
+            }
+
+            // The request shares the buffer, so the data of a large write is passed on without
+            // copying it
+            let mut request = buffer.take();
+            pool.put(buffer);
+
+            // Read ahead of decoding, to still be able to reply, if the request is not known
+            let unique = Session::peek_unique(&request);
+            match decoder.decode(&mut request) {
+                Ok(Some(request)) => Session::dispatch(filesystem, &channel, config, request),
+                Ok(None) => {},
+                Err(error) => {
+                    warn!("Failed to decode request {}: {}", unique, error);
+                    ReplyEmpty::new(unique, channel.clone()).error(ENOSYS);
+                }
+            }
+        }
+    }
+
+    fn peek_unique(buffer: &BytesMut) -> u64 {
+        if buffer.len() < size_of::<fuse_in_header>() {
+            return 0;
+        }
+        // The buffer is not necessarily aligned for the header
+        unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const fuse_in_header) }.unique
+    }
+
+    fn dispatch<FS: Filesystem>(fs: &FS, channel: &Arc<Channel>, config: &Config,
+                                request: crate::request::FuseRequest) {
+        let (header, body) = request.into_parts();
+        let (unique, ino) = (header.unique, header.nodeid);
+        let channel = channel.clone();
+        let req = Request { header: header.clone() };
+
+        match body {
+            Init(arg) => {
+                let body = match arg.minor {
+                    minor if minor < 23 => FuseResponseBody::InitCompat,
+                    _ => FuseResponseBody::Init,
+                };
+                let reply = ReplyInit::new(unique, channel, body);
+                if arg.major < FUSE_KERNEL_VERSION || (arg.major == FUSE_KERNEL_VERSION &&
+                    arg.minor < FUSE_KERNEL_MINOR_VERSION_MIN) {
+                    error!("Unsupported FUSE protocol {}.{}", arg.major, arg.minor);
+                    reply.error(EPROTO);
+                    return;
+                }
+                match fs.init(&req) {
+                    Ok(()) => reply.init(arg.max_readahead.min(config.max_readahead),
+                                         arg.flags & INIT_FLAGS, config.max_write,
+                                         config.max_pages()),
+                    Err(error) => reply.error(error),
+                }
+            },
+            Destroy() => {
+                fs.destroy(&req);
+                ReplyEmpty::new(unique, channel).ok();
+            },
+            Interrupt(arg) => fs.interrupt(&req, arg.unique),
+            Lookup(name) =>
+                fs.lookup(&req, ino, &name, ReplyEntry::new(unique, channel, FuseResponseBody::Lookup)),
+            Forget(arg) => fs.forget(&req, ino, arg.nlookup),
+            BatchForget(nodes) => {
+                for node in nodes {
+                    fs.forget(&req, node.nodeid, node.nlookup);
+                }
+            },
+            GetAttr() =>
+                fs.getattr(&req, ino, ReplyAttr::new(unique, channel, FuseResponseBody::GetAttr)),
+            SetAttr(arg) => {
+                let valid = |flag: u32| arg.valid & flag != 0;
+                fs.setattr(&req, ino,
+                           if valid(FATTR_MODE) { Some(arg.mode) } else { None },
+                           if valid(FATTR_UID) { Some(arg.uid) } else { None },
+                           if valid(FATTR_GID) { Some(arg.gid) } else { None },
+                           if valid(FATTR_SIZE) { Some(arg.size) } else { None },
+                           if valid(FATTR_ATIME) {
+                               Some(system_time_compose(arg.atime, arg.atimensec))
+                           } else { None },
+                           if valid(FATTR_MTIME) {
+                               Some(system_time_compose(arg.mtime, arg.mtimensec))
+                           } else { None },
+                           if valid(FATTR_FH) { Some(arg.fh) } else { None },
+                           ReplyAttr::new(unique, channel, FuseResponseBody::SetAttr))
+            },
+            ReadLink() =>
+                fs.readlink(&req, ino, ReplyData::new(unique, channel, FuseResponseBody::ReadLink)),
+            MkNod(arg, name) =>
+                fs.mknod(&req, ino, &name, arg.mode, arg.rdev,
+                         ReplyEntry::new(unique, channel, FuseResponseBody::MkNod)),
+            MkDir(arg, name) =>
+                fs.mkdir(&req, ino, &name, arg.mode,
+                         ReplyEntry::new(unique, channel, FuseResponseBody::MkDir)),
+            Unlink(name) => fs.unlink(&req, ino, &name, ReplyEmpty::new(unique, channel)),
+            RmDir(name) => fs.rmdir(&req, ino, &name, ReplyEmpty::new(unique, channel)),
+            Symlink(name, link) =>
+                fs.symlink(&req, ino, &name, &link,
+                           ReplyEntry::new(unique, channel, FuseResponseBody::Symlink)),
+            Rename(arg, name, newname) =>
+                fs.rename(&req, ino, &name, arg.newdir, &newname,
+                          ReplyEmpty::new(unique, channel)),
+            Link(arg, newname) =>
+                fs.link(&req, arg.oldnodeid, ino, &newname,
+                        ReplyEntry::new(unique, channel, FuseResponseBody::Link)),
+            Open(arg) =>
+                fs.open(&req, ino, arg.flags, ReplyOpen::new(unique, channel, FuseResponseBody::Open)),
+            Read(arg) =>
+                fs.read(&req, ino, arg.fh, arg.offset, arg.size,
+                        ReplyData::new(unique, channel, FuseResponseBody::Read)),
+            Write(arg, data) => {
+                let data = match data.len() {
+                    len if len < MAX_COPIED_WRITE => Bytes::from(&data[..]),
+                    _ => data,
+                };
+                fs.write(&req, ino, arg.fh, arg.offset, data, arg.write_flags,
+                         ReplyWrite::new(unique, channel, FuseResponseBody::Write))
+            },
+            Flush(arg) =>
+                fs.flush(&req, ino, arg.fh, arg.lock_owner, ReplyEmpty::new(unique, channel)),
+            Release(arg) =>
+                fs.release(&req, ino, arg.fh, arg.flags, arg.lock_owner,
+                           arg.release_flags & FUSE_RELEASE_FLUSH != 0,
+                           ReplyEmpty::new(unique, channel)),
+            FSync(arg) =>
+                fs.fsync(&req, ino, arg.fh, arg.fsync_flags & 1 != 0,
+                         ReplyEmpty::new(unique, channel)),
+            OpenDir(arg) =>
+                fs.opendir(&req, ino, arg.flags,
+                           ReplyOpen::new(unique, channel, FuseResponseBody::OpenDir)),
+            ReadDir(arg) =>
+                fs.readdir(&req, ino, arg.fh, arg.offset,
+                           ReplyDirectory::new(unique, channel, arg.size as usize)),
+            ReleaseDir(arg) =>
+                fs.releasedir(&req, ino, arg.fh, arg.flags, ReplyEmpty::new(unique, channel)),
+            FSyncDir(arg) =>
+                fs.fsyncdir(&req, ino, arg.fh, arg.fsync_flags & 1 != 0,
+                            ReplyEmpty::new(unique, channel)),
+            StatFS() =>
+                fs.statfs(&req, ino, ReplyStatfs::new(unique, channel, FuseResponseBody::StatFS)),
+            SetXAttr(arg, name, value) =>
+                fs.setxattr(&req, ino, &name, &value, arg.flags, ReplyEmpty::new(unique, channel)),
+            GetXAttr(arg, name) =>
+                fs.getxattr(&req, ino, &name, arg.size,
+                            ReplyXattr::new(unique, channel, FuseResponseBody::GetXAttr)),
+            ListXAttr(arg) =>
+                fs.listxattr(&req, ino, arg.size,
+                             ReplyXattr::new(unique, channel, FuseResponseBody::ListXAttr)),
+            RemoveXAttr(name) =>
+                fs.removexattr(&req, ino, &name, ReplyEmpty::new(unique, channel)),
+            Access(arg) => fs.access(&req, ino, arg.mask, ReplyEmpty::new(unique, channel)),
+            Create(arg, name) =>
+                fs.create(&req, ino, &name, arg.mode, arg.flags,
+                          ReplyCreate::new(unique, channel,
+                                           |(entry, open)| FuseResponseBody::Create(entry, open))),
+            GetLock(arg) =>
+                fs.getlk(&req, ino, arg.fh, arg.owner, arg.lk.start, arg.lk.end, arg.lk.typ,
+                         arg.lk.pid, ReplyLock::new(unique, channel, FuseResponseBody::GetLock)),
+            SetLock(arg) => {
+                let sleep = header.opcode == fuse_opcode::FUSE_SETLKW as u32;
+                if arg.lk_flags & FUSE_LK_FLOCK != 0 {
+                    fs.flock(&req, ino, arg.fh, arg.lk.typ, arg.lk.pid, sleep,
+                             ReplyEmpty::new(unique, channel))
+                } else {
+                    fs.setlk(&req, ino, arg.fh, arg.owner, arg.lk.start, arg.lk.end, arg.lk.typ,
+                             arg.lk.pid, sleep, ReplyEmpty::new(unique, channel))
+                }
+            },
+            Bmap(arg) =>
+                fs.bmap(&req, ino, arg.blocksize, arg.block,
+                        ReplyBmap::new(unique, channel, FuseResponseBody::Bmap)),
+
+            #[cfg(target_os = "macos")]
+            SetVolumeName(_) | Exchange(..) | GetXTimes() =>
+                ReplyEmpty::new(unique, channel).error(ENOSYS),
+        }
+    }
+
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Unmounting ends the connection, which stops the workers
+        channel::unmount(&self.mount_point);
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BufMut;
+
+    use crate::encoder::as_u8_slice;
+
+    struct Nothing;
+
+    impl Filesystem for Nothing {}
+
+    fn request<T>(opcode: fuse_opcode, arg: &T, data: &[u8]) -> Vec<u8> {
+        let len = size_of::<fuse_in_header>() + size_of::<T>() + data.len();
+        let header = fuse_in_header {
+            len: len as u32,
+            opcode: opcode as u32,
+            unique: 1,
+            nodeid: FUSE_ROOT_ID,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+            padding: 0,
+        };
+        let mut bytes = Vec::with_capacity(len);
+        bytes.extend_from_slice(as_u8_slice(&header));
+        bytes.extend_from_slice(as_u8_slice(arg));
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Dispatches an INIT of protocol 7.`minor`, that offers `flags`.
+    /// Returns the header and the body of the reply.
+    fn init(minor: u32, flags: u32) -> (fuse_out_header, Vec<u8>) {
+        let (channel, kernel) = Channel::pair().unwrap();
+        let arg = fuse_init_in {
+            major: FUSE_KERNEL_VERSION,
+            minor,
+            max_readahead: 128 * 1024,
+            flags,
+        };
+        let mut bytes = BytesMut::from(request(fuse_opcode::FUSE_INIT, &arg, &[]));
+        let request = FuseRequestDecoder::new().decode(&mut bytes).unwrap().unwrap();
+        Session::dispatch(&Nothing, &Arc::new(channel), &Config::default(), request);
+
+        let mut reply = BytesMut::with_capacity(4096);
+        assert!(kernel.receive(&mut reply).unwrap());
+        let header_len = size_of::<fuse_out_header>();
+        let header = unsafe { std::ptr::read_unaligned(reply.as_ptr() as *const fuse_out_header) };
+        assert_eq!(header.len as usize, reply.len());
+        (header, reply[header_len..].to_vec())
+    }
+
+    fn init_out(body: &[u8]) -> fuse_init_out {
+        assert_eq!(body.len(), size_of::<fuse_init_out>());
+        unsafe { std::ptr::read_unaligned(body.as_ptr() as *const fuse_init_out) }
+    }
+
+    #[test]
+    fn init_reply() {
+        let (header, body) = init(FUSE_KERNEL_MINOR_VERSION, !0);
+        assert_eq!(header.error, 0);
+        let out = init_out(&body);
+        assert_eq!((out.major, out.minor), (FUSE_KERNEL_VERSION, FUSE_KERNEL_MINOR_VERSION));
+        assert_eq!(out.flags, INIT_FLAGS);
+        assert_eq!(out.max_readahead, 128 * 1024);
+        assert_eq!(out.max_write, 1024 * 1024);
+        assert_eq!(out.max_pages, 256);
+
+        let (_, body) = init(FUSE_KERNEL_MINOR_VERSION, FUSE_ASYNC_READ);
+        assert_eq!(init_out(&body).flags, FUSE_ASYNC_READ);
+    }
+
+    #[test]
+    fn init_locks() {
+        // Without POSIX_LOCKS the kernel keeps the locks itself and never sends GETLK or SETLK
+        let (_, body) = init(FUSE_KERNEL_MINOR_VERSION, FUSE_ASYNC_READ | FUSE_POSIX_LOCKS);
+        assert_eq!(init_out(&body).flags, FUSE_ASYNC_READ | FUSE_POSIX_LOCKS);
+
+        let (_, body) = init(FUSE_KERNEL_MINOR_VERSION, FUSE_FLOCK_LOCKS);
+        assert_eq!(init_out(&body).flags, FUSE_FLOCK_LOCKS);
+    }
+
+    #[test]
+    fn init_compat() {
+        let (header, body) = init(22, FUSE_ASYNC_READ);
+        assert_eq!(header.error, 0);
+        assert_eq!(body.len(), FUSE_COMPAT_22_INIT_OUT_SIZE);
+        // The compat reply ends with max_write
+        assert_eq!(&body[20..24], &(1024u32 * 1024).to_ne_bytes());
+    }
+
+    #[test]
+    fn init_unsupported() {
+        let (header, body) = init(FUSE_KERNEL_MINOR_VERSION_MIN - 1, 0);
+        assert_eq!(header.error, -EPROTO);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn largest_write() {
+        let config = Config::default();
+        let arg = fuse_write_in {
+            fh: 1,
+            offset: 0,
+            size: config.max_write,
+            write_flags: 0,
+            lock_owner: 0,
+            flags: 0,
+            padding: 0,
+        };
+        let data = vec![7u8; config.max_write as usize];
+
+        // Panics, if the request does not fit into the buffer
+        let mut buffer = BufferPool::new(config.buffer_size()).get();
+        buffer.put_slice(&request(fuse_opcode::FUSE_WRITE, &arg, &data));
+
+        match FuseRequestDecoder::new().decode(&mut buffer).unwrap().unwrap().into_parts().1 {
+            Write(_, written) => assert_eq!(written.len(), data.len()),
+            _ => panic!("Not decoded as a write"),
+        }
+    }
+
+}
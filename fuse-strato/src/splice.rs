@@ -0,0 +1,325 @@
+use std::io;
+use std::cell::RefCell;
+use std::os::unix::io::RawFd;
+
+use libc::{c_uint, c_void};
+
+use fuse_sys::abi::fuse_out_header;
+
+use crate::encoder::as_u8_slice;
+
+// Data is moved between file descriptors with splice(2), which passes the pages through a pipe
+// instead of copying them into userspace. This is only supported on Linux, elsewhere every
+// attempt fails and the callers fall back to reading and writing.
+
+/// The room a pipe needs beyond the data. Data at an unaligned offset takes up one page more,
+/// and the header of a reply takes up a page of its own.
+const PIPE_SLACK: usize = 2 * PAGE_SIZE;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Returns the largest amount of data, that is spliced into a reply. Its pipes must not be
+/// larger than `/proc/sys/fs/pipe-max-size`, which unprivileged processes can not exceed.
+pub(crate) fn max_len() -> io::Result<usize> {
+    // The kernel rounds the size of a pipe up to a power of two pages
+    let pages = sys::pipe_max_size()? / PAGE_SIZE;
+    if pages == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The pipes hold less than a page"));
+    }
+    let size = (1 << (usize::BITS - 1 - pages.leading_zeros())) * PAGE_SIZE;
+    Ok(size.saturating_sub(PIPE_SLACK))
+}
+
+/// A pipe, through which data is spliced
+#[derive(Debug)]
+pub(crate) struct Pipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Pipe {
+
+    /// Creates a pipe, that holds at least `size` bytes. Unprivileged processes can not grow a
+    /// pipe beyond `/proc/sys/fs/pipe-max-size`.
+    pub(crate) fn new(size: usize) -> io::Result<Self> {
+        let (read, write) = sys::pipe()?;
+        let pipe = Pipe { read, write };
+        sys::set_pipe_size(pipe.write, size)?;
+        Ok(pipe)
+    }
+
+    /// Splices up to `len` bytes from `fd` into the pipe. Returns 0 at the end of the file.
+    pub(crate) fn splice_from(&self, fd: RawFd, offset: Option<&mut i64>, len: usize,
+                              flags: c_uint) -> io::Result<usize> {
+        sys::splice(fd, offset, self.write, len, flags)
+    }
+
+    /// Splices up to `len` bytes from the pipe into `fd`
+    pub(crate) fn splice_to(&self, fd: RawFd, len: usize, flags: c_uint) -> io::Result<usize> {
+        sys::splice(self.read, None, fd, len, flags)
+    }
+
+    /// Writes `data` into the pipe, which must have room for it
+    fn write_all(&self, data: &[u8]) -> io::Result<()> {
+        let written = unsafe {
+            libc::write(self.write, data.as_ptr() as *const c_void, data.len())
+        };
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if written as usize != data.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "The pipe is full"));
+        }
+        Ok(())
+    }
+
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+
+thread_local! {
+    // The pipes of the replies sent from this thread. They are empty between replies.
+    static PIPES: RefCell<Option<(Pipe, Pipe)>> = const { RefCell::new(None) };
+}
+
+/// A reply to a read, whose header and data wait in a pipe to be spliced into the channel
+#[derive(Debug)]
+pub(crate) struct SplicedReply {
+    // The data is spliced into the first pipe, and then behind the header into the second one
+    pipes: (Pipe, Pipe),
+    len: usize,
+}
+
+impl SplicedReply {
+
+    /// Splices up to `len` bytes of `fd` at `offset` into the reply to request `unique`.
+    /// Fewer bytes are sent at the end of the file. The pipes of a thread are created once,
+    /// with room for replies of up to `max_read` bytes, which must not exceed `max_len`.
+    pub(crate) fn new(unique: u64, fd: RawFd, offset: i64, len: usize, max_read: usize)
+        -> io::Result<Self> {
+        if len > max_read {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "The reply is larger than the pipes"));
+        }
+        let header_len = std::mem::size_of::<fuse_out_header>();
+        let (data, reply) = match PIPES.with(|pipes| pipes.borrow_mut().take()) {
+            Some(pipes) => pipes,
+            None => (Pipe::new(max_read + PIPE_SLACK)?, Pipe::new(max_read + PIPE_SLACK)?),
+        };
+
+        // The length of the data is only known, once it is in the pipe
+        let mut offset = offset;
+        let mut spliced = 0;
+        while spliced < len {
+            let flags = sys::SPLICE_F_MOVE;
+            match data.splice_from(fd, Some(&mut offset), len - spliced, flags)? {
+                0 => break,
+                count => spliced += count,
+            }
+        }
+
+        let header = fuse_out_header {
+            len: (header_len + spliced) as u32,
+            error: 0,
+            unique,
+        };
+        reply.write_all(as_u8_slice(&header))?;
+
+        let mut moved = 0;
+        while moved < spliced {
+            moved += data.splice_to(reply.write, spliced - moved, sys::SPLICE_F_MOVE)?;
+        }
+
+        Ok(SplicedReply {
+            pipes: (data, reply),
+            len: header_len + spliced,
+        })
+    }
+
+    /// Splices the reply into `fd`, which has to take it in one piece.
+    /// Pages are moved instead of copied, if `move_pages` is set.
+    pub(crate) fn send(self, fd: RawFd, move_pages: bool) -> io::Result<()> {
+        let flags = if move_pages { sys::SPLICE_F_MOVE } else { 0 };
+        let sent = self.pipes.1.splice_to(fd, self.len, flags)?;
+        if sent != self.len {
+            // The remainder would be taken for the next reply
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "The reply was cut off"));
+        }
+
+        // The pipes are only reused, if they are empty
+        PIPES.with(|pipes| *pipes.borrow_mut() = Some(self.pipes));
+        Ok(())
+    }
+
+}
+
+
+/// Reads up to `len` bytes of `fd` at `offset`. This is the fallback, if splicing fails.
+pub(crate) fn read_at(fd: RawFd, offset: i64, len: usize) -> io::Result<Vec<u8>> {
+    let mut data: Vec<u8> = Vec::with_capacity(len);
+    while data.len() < len {
+        let read = unsafe {
+            let free = data.as_mut_ptr().add(data.len()) as *mut c_void;
+            libc::pread(fd, free, len - data.len(), offset + data.len() as i64)
+        };
+        match read {
+            0 => break,
+            read if read < 0 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            },
+            read => unsafe { data.set_len(data.len() + read as usize) },
+        }
+    }
+    Ok(data)
+}
+
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::*;
+
+    use std::ptr;
+
+    use libc::{c_int, loff_t};
+
+    pub(crate) const SPLICE_F_MOVE: c_uint = libc::SPLICE_F_MOVE;
+
+    pub(super) fn pipe() -> io::Result<(RawFd, RawFd)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((fds[0], fds[1]))
+    }
+
+    pub(super) fn pipe_max_size() -> io::Result<usize> {
+        let size = std::fs::read_to_string("/proc/sys/fs/pipe-max-size")?;
+        size.trim().parse()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Returns the size, the kernel rounded up to
+    pub(super) fn set_pipe_size(fd: RawFd, size: usize) -> io::Result<usize> {
+        let size = unsafe { libc::fcntl(fd, libc::F_SETPIPE_SZ, size as c_int) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size as usize)
+    }
+
+    pub(super) fn splice(from: RawFd, offset: Option<&mut i64>, to: RawFd, len: usize,
+                         flags: c_uint) -> io::Result<usize> {
+        let offset = match offset {
+            Some(offset) => offset as *mut i64 as *mut loff_t,
+            None => ptr::null_mut(),
+        };
+        let spliced = unsafe { libc::splice(from, offset, to, ptr::null_mut(), len, flags) };
+        if spliced < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(spliced as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::*;
+
+    pub(crate) const SPLICE_F_MOVE: c_uint = 0;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "Splicing is only supported on Linux")
+    }
+
+    pub(super) fn pipe() -> io::Result<(RawFd, RawFd)> {
+        Err(unsupported())
+    }
+
+    pub(super) fn pipe_max_size() -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    pub(super) fn set_pipe_size(_fd: RawFd, _size: usize) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    pub(super) fn splice(_from: RawFd, _offset: Option<&mut i64>, _to: RawFd, _len: usize,
+                         _flags: c_uint) -> io::Result<usize> {
+        Err(unsupported())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn splice() {
+        let mut file = tempfile("splice");
+        file.write_all(b"Hello, world!").unwrap();
+
+        let reply = SplicedReply::new(7, file.as_raw_fd(), 7, 64, 4096).unwrap();
+        assert_eq!(reply.len, 16 + 6);
+
+        // Receives the reply through a pipe in place of the channel
+        let channel = Pipe::new(PIPE_SLACK).unwrap();
+        reply.send(channel.write, false).unwrap();
+
+        let mut buffer = [0u8; 64];
+        let read = unsafe {
+            libc::read(channel.read, buffer.as_mut_ptr() as *mut c_void, buffer.len())
+        };
+        assert_eq!(read, 16 + 6);
+        assert_eq!(&buffer[0..4], &22u32.to_ne_bytes());
+        assert_eq!(&buffer[8..16], &7u64.to_ne_bytes());
+        assert_eq!(&buffer[16..22], b"world!");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn max_len() {
+        let max_len = super::max_len().unwrap();
+        assert!((max_len + PIPE_SLACK).is_power_of_two());
+        assert!(max_len + PIPE_SLACK <= sys::pipe_max_size().unwrap());
+
+        // Pipes of this size can be created without privileges
+        SplicedReply::new(1, 0, 0, 0, max_len).unwrap();
+    }
+
+    #[test]
+    fn read() {
+        let mut file = tempfile("read");
+        file.write_all(b"Hello, world!").unwrap();
+
+        assert_eq!(read_at(file.as_raw_fd(), 7, 64).unwrap(), b"world!");
+        assert_eq!(read_at(file.as_raw_fd(), 0, 5).unwrap(), b"Hello");
+    }
+
+    fn tempfile(name: &str) -> File {
+        let path = std::env::temp_dir()
+            .join(format!("fuse-strato-{}-{}", name, std::process::id()));
+        let file = std::fs::OpenOptions::new()
+            .read(true).write(true).create(true).truncate(true)
+            .open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+
+}
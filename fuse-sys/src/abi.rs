@@ -18,10 +18,16 @@
 //!
 //! Items without a version annotation are valid with ABI 7.8 and later
 
-// TODO: We currently target ABI 7.8, which is very conservative and missing many newer
-// features. We still need to figure out a way to support different ABI version without hazzle.
+// We target ABI 7.28, but accept kernels down to ABI 7.12. Since 7.12, the kernel sends the
+// same requests regardless of its version, only the reply to init grew with ABI 7.23.
 pub const FUSE_KERNEL_VERSION: u32 = 7;
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 8;
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 28;
+
+// The oldest ABI, that is supported
+pub const FUSE_KERNEL_MINOR_VERSION_MIN: u32 = 12;
+
+// The size of the reply to init up to ABI 7.22
+pub const FUSE_COMPAT_22_INIT_OUT_SIZE: usize = 24;
 
 pub const FUSE_ROOT_ID: u64 = 1;
 
@@ -48,6 +54,8 @@ pub struct fuse_attr {
     pub rdev: u32,
     #[cfg(target_os = "macos")]
     pub flags: u32,                                     // see chflags(2)
+    pub blksize: u32,                                   // since ABI 7.9
+    pub padding: u32,                                   // since ABI 7.9
 }
 
 #[repr(C)]
@@ -103,6 +111,9 @@ pub mod consts {
     // Init request/reply flags
     pub const FUSE_ASYNC_READ: u32          = 1 << 0;
     pub const FUSE_POSIX_LOCKS: u32         = 1 << 1;
+    pub const FUSE_BIG_WRITES: u32          = 1 << 5;   // since ABI 7.9
+    pub const FUSE_FLOCK_LOCKS: u32         = 1 << 10;  // since ABI 7.17
+    pub const FUSE_MAX_PAGES: u32           = 1 << 22;  // since ABI 7.28
     #[cfg(target_os = "macos")]
     pub const FUSE_CASE_INSENSITIVE: u32    = 1 << 29;
     #[cfg(target_os = "macos")]
@@ -113,6 +124,9 @@ pub mod consts {
     // Release flags
     pub const FUSE_RELEASE_FLUSH: u32       = 1 << 0;
 
+    // Lock flags
+    pub const FUSE_LK_FLOCK: u32            = 1 << 0;   // since ABI 7.9
+
     // The read buffer is required to be at least 8k, but may be much larger
     pub const FUSE_MIN_READ_BUFFER: usize   = 8192;
 }
@@ -157,6 +171,7 @@ pub enum fuse_opcode {
     FUSE_INTERRUPT = 36,
     FUSE_BMAP = 37,
     FUSE_DESTROY = 38,
+    FUSE_BATCH_FORGET = 42,                             // since ABI 7.16, no reply
     #[cfg(target_os = "macos")]
     FUSE_SETVOLNAME = 61,
     #[cfg(target_os = "macos")]
@@ -205,6 +220,7 @@ impl fuse_opcode {
             36 => Some(fuse_opcode::FUSE_INTERRUPT),
             37 => Some(fuse_opcode::FUSE_BMAP),
             38 => Some(fuse_opcode::FUSE_DESTROY),
+            42 => Some(fuse_opcode::FUSE_BATCH_FORGET),
             #[cfg(target_os = "macos")]
             61 => Some(fuse_opcode::FUSE_SETVOLNAME),
             #[cfg(target_os = "macos")]
@@ -234,6 +250,21 @@ pub struct fuse_forget_in {
     pub nlookup: u64,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct fuse_forget_one {                            // since ABI 7.16
+    pub nodeid: u64,
+    pub nlookup: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct fuse_batch_forget_in {                       // since ABI 7.16
+    pub count: u32,
+    pub dummy: u32,
+    // followed by count fuse_forget_one
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq)]
 pub struct fuse_attr_out {
@@ -258,6 +289,8 @@ pub struct fuse_getxtimes_out {
 pub struct fuse_mknod_in {
     pub mode: u32,
     pub rdev: u32,
+    pub umask: u32,                                     // since ABI 7.12
+    pub padding: u32,                                   // since ABI 7.12
 }
 
 #[repr(C)]
@@ -330,6 +363,15 @@ pub struct fuse_open_in {
     pub mode: u32,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct fuse_create_in {                             // since ABI 7.12
+    pub flags: u32,
+    pub mode: u32,
+    pub umask: u32,
+    pub padding: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, PartialEq)]
 pub struct fuse_open_out {
@@ -362,6 +404,9 @@ pub struct fuse_read_in {
     pub fh: u64,
     pub offset: i64,
     pub size: u32,
+    pub read_flags: u32,                                // since ABI 7.9
+    pub lock_owner: u64,                                // since ABI 7.9
+    pub flags: u32,                                     // since ABI 7.9
     pub padding: u32,
 }
 
@@ -372,6 +417,9 @@ pub struct fuse_write_in {
     pub offset: i64,
     pub size: u32,
     pub write_flags: u32,
+    pub lock_owner: u64,                                // since ABI 7.9
+    pub flags: u32,                                     // since ABI 7.9
+    pub padding: u32,                                   // since ABI 7.9
 }
 
 #[repr(C)]
@@ -430,6 +478,8 @@ pub struct fuse_lk_in {
     pub fh: u64,
     pub owner: u64,
     pub lk: fuse_file_lock,
+    pub lk_flags: u32,                                  // since ABI 7.9
+    pub padding: u32,                                   // since ABI 7.9
 }
 
 #[repr(C)]
@@ -461,8 +511,13 @@ pub struct fuse_init_out {
     pub minor: u32,
     pub max_readahead: u32,
     pub flags: u32,
-    pub unused: u32,
+    pub max_background: u16,                            // since ABI 7.13
+    pub congestion_threshold: u16,                      // since ABI 7.13
     pub max_write: u32,
+    pub time_gran: u32,                                 // since ABI 7.23
+    pub max_pages: u16,                                 // since ABI 7.28
+    pub padding: u16,                                   // since ABI 7.28
+    pub unused: [u32; 8],                               // since ABI 7.23
 }
 
 #[repr(C)]
@@ -1,9 +1,12 @@
 extern crate env_logger;
 
 use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::ops::Deref;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::thread;
+use std::time::Duration;
 use std::env;
 use std::process::Command;
 
@@ -16,16 +19,22 @@ use tokio::prelude::*;
 
 use time;
 
-use strato::{Node, Directory, File, Request};
+use strato::{Node, Directory, File, StreamFile, Symlink, SpecialFile, SpecialKind};
+use strato::{FileStream, Request, DirectoryReply, Bytes, ReadData};
 use strato::error::{FileError, DirError, NodeError};
-use strato::Handle;
+use strato::{Handle, FileHandle};
 use strato::Engine;
 use strato::Controller;
-use strato::link::NodeEntry;
+use strato::link::{NodeEntry, AttrChanges};
 
 struct StaticDirInner {
     handle: Option<Handle>,
-    links : Vec<NodeEntry>
+    controller: Option<Controller>,
+    // The entries by their readdir cookie, which is never reused
+    links : BTreeMap<u64, NodeEntry>,
+    next_cookie : u64,
+    // The subdirectories, that were created from userspace
+    subdirs : Vec<(String, StaticDir)>,
 }
 
 #[derive(Clone)]
@@ -44,66 +53,304 @@ impl StaticDir {
         StaticDir(Arc::new(RwLock::new(
             StaticDirInner{
                 handle: None,
-                links : Vec::new(),
+                controller: None,
+                links : BTreeMap::new(),
+                next_cookie : 1,
+                subdirs : Vec::new(),
             }
         )))
     }
 
-    fn add(&mut self, link: NodeEntry) {
-        self.write().links.push(link);
+    fn add(&self, link: NodeEntry) {
+        let mut inner = self.write();
+        let cookie = inner.next_cookie;
+        inner.next_cookie += 1;
+        inner.links.insert(cookie, link);
+    }
+
+    fn position(&self, name: &str) -> Option<u64> {
+        self.read().links.iter().find(|(_, x)| x.get_name() == name).map(|(cookie, _)| *cookie)
+    }
+
+    fn is_subdir(&self, name: &str) -> bool {
+        self.read().subdirs.iter().any(|(n, _)| n == name)
+    }
+
+    fn remove_subdir(&self, name: &str) -> Result<(), DirError> {
+        let subdir = match self.read().subdirs.iter().position(|(n, _)| n == name) {
+            Some(i) => i,
+            None => return Err(DirError::new(DirError::IsNotDirectory)),
+        };
+
+        if !self.read().subdirs[subdir].1.read().links.is_empty() {
+            return Err(DirError::new(DirError::DirectoryNotEmpty));
+        }
+
+        let i = self.position(name).unwrap();
+        let mut inner = self.write();
+        inner.links.remove(&i);
+        inner.subdirs.remove(subdir);
+        Ok(())
     }
 
 }
 
 impl Node for StaticDir {
 
-    fn init(&mut self, controller: Controller) {
+    fn init(&self, controller: Controller) {
         println!("Init on static dir");
         self.write().handle = Some(controller.get_handle());
+        self.write().controller = Some(controller);
     }
 
-    fn read_attributes(&mut self, _req: Request,
-                       mut attr: NodeEntry) -> Result<NodeEntry, NodeError> {
+    fn read_attributes(&self, _req: Request, mut attr: NodeEntry)
+            -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
         //println!("Requested attributes on static dir");
 
         attr.mtime(time::get_time());
-        attr.ttl(time::get_time() + time::Duration::seconds(20));
+        attr.ttl(Duration::from_secs(20));
 
-        Ok(attr)
+        Box::new(future::ok(attr))
     }
 
 }
 
 impl Directory for StaticDir {
 
-    fn readdir(&mut self, _req: Request) -> Result<Vec<NodeEntry>, DirError> {
-        println!("Readdir on static dir");
-        let mut vec = vec!{
-                NodeEntry::new(".".to_string(), self.read().handle.clone().unwrap()),
-                NodeEntry::new("..".to_string(), self.read().handle.clone().unwrap()),
-            };
-        vec.append(&mut self.read().links.clone());
-        Ok(vec)
+    fn readdir(&self, _req: Request, _fh: FileHandle, cookie: u64,
+               mut reply: DirectoryReply)
+            -> Box<dyn Future<Item=DirectoryReply, Error=DirError> + Send> {
+        println!("Readdir on static dir, cookie: {}", cookie);
+        let inner = self.read();
+
+        // Entries added or removed meanwhile do not shift the entries after the cookie
+        for (c, entry) in inner.links.range(cookie + 1..) {
+            if reply.add(entry, *c) {
+                break;
+            }
+        }
+        Box::new(future::ok(reply))
     }
 
-    fn lookup(&mut self, _req: Request, name: String) -> Result<NodeEntry, NodeError> {
+    fn lookup(&self, _req: Request, name: String)
+            -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
         println!("Lookup on static dir, name: {}", name);
-        if name == "." || name == ".." {
-            return Ok(NodeEntry::new(name, self.read().handle.clone().unwrap()))
-        } else {
-            for x in self.read().links.iter() {
-                if x.get_name() == name {
-                    return Ok(x.clone());
-                }
+        for x in self.read().links.values() {
+            if x.get_name() == name {
+                return Box::new(future::ok(x.clone()));
+            }
+        }
+        Box::new(future::err(NodeError::new(NodeError::NoSuchEntry)))
+    }
+
+    fn create(&self, _req: Request, name: String, _mode: u32, _flags: u32)
+            -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        println!("Create on static dir, name: {}", name);
+        if self.position(&name).is_some() {
+            return Box::new(future::err(FileError::new(FileError::FileExists)));
+        }
+
+        let handle = self.write().controller.as_mut().unwrap().add_file(MemoryFile::new());
+        let entry = NodeEntry::new(name, handle);
+        self.add(entry.clone());
+        Box::new(future::ok(entry))
+    }
+
+    fn mknod(&self, _req: Request, name: String, mode: u32, rdev: u32)
+            -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        println!("Mknod on static dir, name: {}", name);
+        if self.position(&name).is_some() {
+            return Box::new(future::err(FileError::new(FileError::FileExists)));
+        }
+
+        let handle = {
+            let mut inner = self.write();
+            let controller = inner.controller.as_mut().unwrap();
+            match SpecialKind::from_mode(mode) {
+                Some(kind) => controller.add_special(SpecialNode{ kind, rdev }),
+                None => controller.add_file(MemoryFile::new()),
             }
         };
-        Err(NodeError::new(NodeError::NoSuchEntry))
+
+        let entry = NodeEntry::new(name, handle);
+        self.add(entry.clone());
+        Box::new(future::ok(entry))
+    }
+
+    fn mkdir(&self, _req: Request, name: String, _mode: u32)
+            -> Box<dyn Future<Item=NodeEntry, Error=DirError> + Send> {
+        println!("Mkdir on static dir, name: {}", name);
+        if self.position(&name).is_some() {
+            return Box::new(future::err(DirError::new(DirError::DirectoryExists)));
+        }
+
+        let dir = StaticDir::new();
+        let handle = self.write().controller.as_mut().unwrap().add_directory(dir.clone());
+        let entry = NodeEntry::new(name.clone(), handle);
+        self.add(entry.clone());
+        self.write().subdirs.push((name, dir));
+        Box::new(future::ok(entry))
+    }
+
+    fn unlink(&self, _req: Request, name: String)
+            -> Box<dyn Future<Item=(), Error=FileError> + Send> {
+        println!("Unlink on static dir, name: {}", name);
+        if self.is_subdir(&name) {
+            return Box::new(future::err(FileError::new(FileError::IsDirectory)));
+        }
+
+        match self.position(&name) {
+            Some(i) => {
+                self.write().links.remove(&i);
+                Box::new(future::ok(()))
+            },
+            None => Box::new(future::err(FileError::new(FileError::NoSuchFile))),
+        }
+    }
+
+    fn rmdir(&self, _req: Request, name: String)
+            -> Box<dyn Future<Item=(), Error=DirError> + Send> {
+        println!("Rmdir on static dir, name: {}", name);
+        Box::new(future::result(self.remove_subdir(&name)))
+    }
+
+    fn symlink(&self, _req: Request, name: String, target: PathBuf)
+            -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        println!("Symlink on static dir, name: {}", name);
+        if self.position(&name).is_some() {
+            return Box::new(future::err(FileError::new(FileError::FileExists)));
+        }
+
+        let handle = self.write().controller.as_mut().unwrap().add_symlink(StaticLink(target));
+        let entry = NodeEntry::new(name, handle);
+        self.add(entry.clone());
+        Box::new(future::ok(entry))
+    }
+
+    fn rename(&self, _req: Request, name: String, new_name: String)
+            -> Box<dyn Future<Item=(), Error=DirError> + Send> {
+        println!("Rename on static dir, {} to {}", name, new_name);
+        let i = match self.position(&name) {
+            Some(i) => i,
+            None => return Box::new(future::err(DirError::new(NodeError::NoSuchEntry))),
+        };
+
+        if name == new_name {
+            return Box::new(future::ok(()));
+        }
+
+        if self.is_subdir(&new_name) {
+            if let Err(error) = self.remove_subdir(&new_name) {
+                return Box::new(future::err(error));
+            }
+        } else if let Some(j) = self.position(&new_name) {
+            self.write().links.remove(&j);
+        }
+
+        let mut inner = self.write();
+        let handle = inner.links[&i].get_handle();
+        inner.links.insert(i, NodeEntry::new(new_name.clone(), handle));
+        for (n, _) in inner.subdirs.iter_mut().filter(|(n, _)| *n == name) {
+            *n = new_name.clone();
+        }
+        Box::new(future::ok(()))
     }
 }
 
+/// A symbolic link to a fixed target
+struct StaticLink(PathBuf);
+
+impl Node for StaticLink {}
+
+impl Symlink for StaticLink {
+
+    fn readlink(&self, _req: Request) -> Box<dyn Future<Item=PathBuf, Error=NodeError> + Send> {
+        Box::new(future::ok(self.0.clone()))
+    }
+
+}
+
+/// A pipe, socket or device, created via `mknod`
+struct SpecialNode {
+    kind: SpecialKind,
+    rdev: u32,
+}
+
+impl Node for SpecialNode {}
+
+impl SpecialFile for SpecialNode {
+
+    fn kind(&self) -> SpecialKind {
+        self.kind
+    }
+
+    fn rdev(&self) -> u32 {
+        self.rdev
+    }
+
+}
+
+/// A file, that holds its content in memory and can be written to
+#[derive(Clone)]
+struct MemoryFile(Arc<RwLock<Vec<u8>>>);
+
+impl MemoryFile {
+
+    fn new() -> Self {
+        MemoryFile(Arc::new(RwLock::new(Vec::new())))
+    }
+
+}
+
+impl Node for MemoryFile {
+
+    fn read_attributes(&self, _req: Request, mut attr: NodeEntry)
+            -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
+        attr.size(self.0.read().len() as u64);
+        attr.ttl(Duration::from_secs(1));
+        Box::new(future::ok(attr))
+    }
+
+    fn set_attributes(&self, _req: Request, changes: AttrChanges, mut attr: NodeEntry)
+            -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
+        if let Some(size) = changes.size() {
+            self.0.write().resize(size as usize, 0);
+        }
+        attr.apply(&changes);
+        Box::new(future::ok(attr))
+    }
+
+}
+
+impl File for MemoryFile {
+
+    fn read(&self, _req: Request, _fh: FileHandle, offset: i64, size: u32)
+            -> Box<dyn Future<Item=ReadData, Error=FileError> + Send> {
+        let content = self.0.read();
+        let start = std::cmp::min(offset as usize, content.len());
+        let end = std::cmp::min(start + size as usize, content.len());
+        Box::new(future::ok(content[start..end].to_vec().into()))
+    }
+
+    fn write(&self, _req: Request, _fh: FileHandle, offset: i64, data: Bytes, _flags: u32)
+            -> Box<dyn Future<Item=u32, Error=FileError> + Send> {
+        let start = match usize::try_from(offset) {
+            Ok(start) => start,
+            Err(_) => return Box::new(future::err(FileError::new(NodeError::InvalidArgument))),
+        };
+        let mut content = self.0.write();
+        let end = start + data.len();
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[start..end].copy_from_slice(&data);
+        Box::new(future::ok(data.len() as u32))
+    }
+
+}
+
 #[derive(Clone, Debug)]
 struct StaticFile {
-    handle: Option<Handle>,
     text: String,
     delay: u32,
 }
@@ -112,7 +359,6 @@ impl StaticFile {
 
     fn new(text: String, delay: u32) -> Self {
         StaticFile{
-            handle: None,
             text,
             delay
         }
@@ -122,29 +368,36 @@ impl StaticFile {
 
 impl Node for StaticFile {
 
-    fn init(&mut self, controller: Controller) {
+    fn init(&self, _controller: Controller) {
         println!("Init on static file");
-        self.handle = Some(controller.get_handle());
     }
 
-    fn read_attributes(&mut self, _req: Request, mut attr: NodeEntry)
-            -> Result<NodeEntry, NodeError> {
+    fn read_attributes(&self, _req: Request, mut attr: NodeEntry)
+            -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
 
         //println!("Requested attributes on static file");
 
         attr.mtime(time::get_time() - time::Duration::seconds(20));
-        attr.ttl(time::get_time() + time::Duration::seconds(1));
+        attr.ttl(Duration::from_secs(1));
 
         attr.size(self.text.len() as u64);
+        attr.perm(0o444);
 
-        Ok(attr)
+        Box::new(future::ok(attr))
     }
 
 }
 
 impl File for StaticFile {
 
-    fn read(&mut self, _req: Request) -> Box<Future<Item=Vec<u8>, Error=FileError> + Send> {
+    fn open(&self, _req: Request, _flags: u32)
+            -> Box<dyn Future<Item=FileHandle, Error=FileError> + Send> {
+        // The text never changes, so the kernel may keep its cache
+        Box::new(future::ok(FileHandle::empty().keep_cache()))
+    }
+
+    fn read(&self, _req: Request, _fh: FileHandle, offset: i64, size: u32)
+            -> Box<dyn Future<Item=ReadData, Error=FileError> + Send> {
         println!("Request read on static file");
 
         let del = std::time::Instant::now() + std::time::Duration::from_secs(self.delay as u64);
@@ -152,7 +405,10 @@ impl File for StaticFile {
 
         Box::new(tokio::timer::Delay::new(del)
             .then( move |_| {
-                future::ok(cl.text.clone().into_bytes())
+                let bytes = cl.text.as_bytes();
+                let start = std::cmp::min(offset as usize, bytes.len());
+                let end = std::cmp::min(start + size as usize, bytes.len());
+                future::ok(bytes[start..end].to_vec().into())
             }))
 
     }
@@ -160,6 +416,25 @@ impl File for StaticFile {
 }
 
 
+/// A file, that produces a number of lines, when it is read
+struct CountingFile {
+    lines: u32,
+}
+
+impl Node for CountingFile {}
+
+impl StreamFile for CountingFile {
+
+    fn open_stream(&self, _req: Request)
+            -> Box<dyn Future<Item=FileStream, Error=FileError> + Send> {
+        println!("Open stream on counting file");
+
+        let lines = (0..self.lines).map(|i| format!("Line {}\n", i).into_bytes());
+        Box::new(future::ok(FileStream::from_stream(stream::iter_ok(lines))))
+    }
+
+}
+
 
 fn main() {
     env_logger::init();
@@ -189,7 +464,7 @@ fn main() {
     // This is the actual user code
     // This should make a nice API someday
 
-    let mut root = StaticDir::new();
+    let root = StaticDir::new();
     let mut engine = Engine::new(&mountpoint, root.clone());
 
 
@@ -199,6 +474,12 @@ fn main() {
     let text_handle = engine.add_file(StaticFile::new("Goodbeye World\n".to_string(), 5));
     root.add(NodeEntry::new("goodbye.txt".to_string(), text_handle));
 
+    let count_handle = engine.add_stream_file(CountingFile{ lines: 1000 });
+    root.add(NodeEntry::new("count.txt".to_string(), count_handle));
+
+    let link_handle = engine.add_symlink(StaticLink(PathBuf::from("hello.txt")));
+    root.add(NodeEntry::new("hello.lnk".to_string(), link_handle));
+
 
     match engine.start() {
         Err(error) => println!("{}", error),
@@ -1,11 +1,17 @@
 use std::sync::Arc;
+use std::path::{Path, PathBuf};
 
-use fuse::Request as FuseRequest;
+use fuse_strato::Request as FuseRequest;
 
-use crate::{Registry, File, Directory};
+use futures::future::Future;
+
+use crate::{Registry, File, Directory, StreamFile, Symlink, SpecialFile};
 use crate::engine::Engine;
 use crate::handler::{Handle, HandleDispatcher::*};
 use crate::utils::InoGenerator;
+use crate::file_handle::FhTable;
+use crate::topology::{Topology, ROOT_INO};
+use crate::error::NodeError;
 
 /// This object gets handed down to functions implementing a File System Handle trait, such as
 /// File or Directory. The controller exposes information about the Handles context and can also
@@ -16,6 +22,8 @@ pub struct Controller {
 
     ino_generator : Arc<InoGenerator>,
     registry : Registry,
+    fh_table : Arc<FhTable>,
+    topology : Arc<Topology>,
 
     handle : Handle,
 }
@@ -30,10 +38,10 @@ impl Controller {
         let ino = self.ino_generator.generate();
         let handle = Handle::new_file(ino, boxed);
 
-        self.registry.write().insert(ino, handle.clone());
+        self.registry.insert(ino, handle.clone());
 
         let controller = Controller::create_from_controller(self, ino, handle.clone());
-        if let RegularFile(ref mut file) = handle.write().dispatch() {
+        if let RegularFile(ref file) = handle.dispatch() {
             file.init(controller)
         } else {
             // Can not happen
@@ -49,10 +57,10 @@ impl Controller {
         let ino = self.ino_generator.generate();
         let handle = Handle::new_dir(ino, boxed);
 
-        self.registry.write().insert(ino,handle.clone());
+        self.registry.insert(ino, handle.clone());
 
         let controller = Controller::create_from_controller(self, ino, handle.clone());
-        if let Dir(ref mut dir) = handle.write().dispatch() {
+        if let Dir(ref dir) = handle.dispatch() {
             dir.init(controller)
         } else {
             // Can not happen
@@ -61,10 +69,118 @@ impl Controller {
         handle
     }
 
+    pub fn add_stream_file<T: 'static>(&mut self, object: T) -> Handle
+        where T: StreamFile + Send + Sync {
+
+        let boxed = Box::new(object);
+        let ino = self.ino_generator.generate();
+        let handle = Handle::new_stream(ino, boxed);
+
+        self.registry.insert(ino, handle.clone());
+
+        let controller = Controller::create_from_controller(self, ino, handle.clone());
+        if let Streamed(ref file) = handle.dispatch() {
+            file.init(controller)
+        } else {
+            // Can not happen
+            panic!();
+        }
+        handle
+    }
+
+    pub fn add_symlink<T: 'static>(&mut self, object: T) -> Handle
+        where T: Symlink + Send + Sync {
+
+        let boxed = Box::new(object);
+        let ino = self.ino_generator.generate();
+        let handle = Handle::new_symlink(ino, boxed);
+
+        self.registry.insert(ino, handle.clone());
+
+        let controller = Controller::create_from_controller(self, ino, handle.clone());
+        if let SymbolicLink(ref link) = handle.dispatch() {
+            link.init(controller)
+        } else {
+            // Can not happen
+            panic!();
+        }
+        handle
+    }
+
+    pub fn add_special<T: 'static>(&mut self, object: T) -> Handle
+        where T: SpecialFile + Send + Sync {
+
+        let boxed = Box::new(object);
+        let ino = self.ino_generator.generate();
+        let handle = Handle::new_special(ino, boxed);
+
+        self.registry.insert(ino, handle.clone());
+
+        let controller = Controller::create_from_controller(self, ino, handle.clone());
+        if let Special(ref node) = handle.dispatch() {
+            node.init(controller)
+        } else {
+            // Can not happen
+            panic!();
+        }
+        handle
+    }
+
     pub fn get_handle(&self) -> Handle {
         self.handle.clone()
     }
 
+    /// Returns the directory, that contains this node. The root is its own parent.
+    /// Returns `None`, if the node was not found in any directory yet.
+    pub fn parent(&self) -> Option<Handle> {
+        if self.this_ino == ROOT_INO {
+            return Some(self.handle.clone());
+        }
+        self.topology.parent(self.this_ino)
+            .and_then(|parent| self.registry.get(parent))
+    }
+
+    /// Returns all known entries of this node, as pairs of the directory and the name.
+    /// A file has several entries, if it is hard linked.
+    pub fn names(&self) -> Vec<(Handle, String)> {
+        self.topology.names(self.this_ino).into_iter()
+            .filter_map(|(parent, name)| self.registry.get(parent).map(|dir| (dir, name)))
+            .collect()
+    }
+
+    /// Returns the absolute path of this node within the file system.
+    /// Returns `None`, if the node was not found in any directory yet.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.topology.path(self.this_ino)
+    }
+
+    /// Returns the node at the absolute `path`. Entries, that were not found yet, are looked
+    /// up in their directories.
+    pub fn resolve(&self, path: &Path) -> Box<dyn Future<Item=Handle, Error=NodeError> + Send> {
+        Topology::resolve(self.topology.clone(), path, self.registry.clone())
+    }
+
+    /// Registers `child` as entry `name` of the directory behind this controller.
+    /// Entries created through file system operations or found by a lookup are registered
+    /// automatically. Nodes created through the file system are counted exactly, so every
+    /// further entry, that the directory adds on its own, must be registered for them.
+    pub fn link(&self, name: &str, child: &Handle) {
+        let (ino, is_dir) = (child.get_ino(), child.is_dir());
+
+        if let Some(replaced) = self.topology.link(self.this_ino, name, ino, is_dir) {
+            self.topology.collect(replaced, &self.registry, &self.fh_table);
+        }
+    }
+
+    /// Removes the entry `name` of the directory behind this controller, that was removed by
+    /// the directory on its own. If it was the last entry of a node created through the file
+    /// system, the node is torn down.
+    pub fn unlink(&self, name: &str) {
+        if let Some(ino) = self.topology.unlink(self.this_ino, name) {
+            self.topology.collect(ino, &self.registry, &self.fh_table);
+        }
+    }
+
 
     pub(crate) fn create_from_engine(engine: &Engine, ino: u64, handle: Handle) -> Self {
         Controller {
@@ -72,6 +188,8 @@ impl Controller {
 
             ino_generator : engine.get_ino_generator(),
             registry : engine.get_registry(),
+            fh_table : engine.get_fh_table(),
+            topology : engine.get_topology(),
 
             handle,
         }
@@ -83,6 +201,8 @@ impl Controller {
 
             ino_generator : controller.ino_generator.clone(),
             registry : controller.registry.clone(),
+            fh_table : controller.fh_table.clone(),
+            topology : controller.topology.clone(),
 
             handle,
         }
@@ -108,6 +228,16 @@ impl Request {
         }
     }
 
+    /// Creates a request on behalf of the file system itself, e.g. to resolve a path
+    pub(crate) fn internal() -> Self {
+        Request {
+            id : 0,
+            uid : unsafe { libc::getuid() },
+            gid : unsafe { libc::getgid() },
+            pid : std::process::id(),
+        }
+    }
+
     pub fn id(&self) -> u64 {
         self.id
     }
@@ -0,0 +1,109 @@
+use fuse_strato::{FileType, ReplyDirectory};
+
+use crate::link::NodeEntry;
+
+/// The size of the header of a directory entry in the reply
+const DIRENT_HEADER: usize = 24;
+
+/// Collects the entries of one `readdir` call, until the buffer of the kernel is full.
+///
+/// Every entry is added with a cookie, that marks the position after it. When the buffer
+/// is full, the kernel asks again with the cookie of the last entry, that was added.
+/// Cookies are opaque to strato, but they must not be 0, which marks the start of the listing,
+/// and must fit into 63 bits.
+/// To neither skip nor repeat entries, while the directory changes, a cookie should stay
+/// valid, even if its entry was removed in the meantime, e.g. by using a counter, that is
+/// assigned to every entry once.
+#[derive(Debug)]
+pub struct DirectoryReply {
+    entries: Vec<(u64, FileType, String, u64)>,
+    // The number of offsets, that are taken by the entries strato adds in front
+    reserved: u64,
+    // The size of the buffer, the kernel provided
+    size: usize,
+    used: usize,
+    full: bool,
+}
+
+impl DirectoryReply {
+
+    pub(crate) fn new(reserved: u64, size: usize) -> Self {
+        DirectoryReply {
+            entries: Vec::new(),
+            reserved,
+            size,
+            used: 0,
+            full: false,
+        }
+    }
+
+    /// Translates the offset, the kernel asks for, into the cookie of the directory.
+    /// Offsets of the entries added by strato translate to the start of the listing.
+    pub(crate) fn to_cookie(&self, offset: u64) -> u64 {
+        offset.saturating_sub(self.reserved)
+    }
+
+    /// Adds an entry in front of the entries of the directory, at the position `offset`.
+    pub(crate) fn add_reserved(&mut self, entry: &NodeEntry, offset: u64) -> bool {
+        debug_assert!(offset > 0 && offset <= self.reserved);
+        self.add_at(entry, offset)
+    }
+
+    /// Adds an entry, that is followed by the position `cookie`.
+    /// Returns true, if the buffer is full and the entry was not added. The directory should
+    /// stop listing entries then.
+    pub fn add(&mut self, entry: &NodeEntry, cookie: u64) -> bool {
+        let offset = cookie + self.reserved;
+        self.add_at(entry, offset)
+    }
+
+    /// Returns true, if no more entries fit into the buffer.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    fn add_at(&mut self, entry: &NodeEntry, offset: u64) -> bool {
+        if self.full {
+            return true;
+        }
+
+        // Entries are padded to 8 bytes
+        let (ino, kind, name) = entry.to_reply();
+        let size = (DIRENT_HEADER + name.len() + 7) & !7;
+        if self.used + size > self.size {
+            self.full = true;
+            return true;
+        }
+
+        self.used += size;
+        self.entries.push((ino, kind, name, offset));
+        false
+    }
+
+    /// Sends the collected entries, which fit into the buffer of `reply`
+    pub(crate) fn send(self, mut reply: ReplyDirectory) {
+        for (ino, kind, name, offset) in self.entries {
+            let full = reply.add(ino, offset as i64, kind, name);
+            debug_assert!(!full, "The entries were collected for a larger buffer");
+        }
+        reply.ok();
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookies() {
+        let reply = DirectoryReply::new(2, 4096);
+        assert_eq!(reply.to_cookie(0), 0);
+        assert_eq!(reply.to_cookie(1), 0);
+        assert_eq!(reply.to_cookie(2), 0);
+        assert_eq!(reply.to_cookie(7), 5);
+        assert_eq!(DirectoryReply::new(0, 4096).to_cookie(7), 7);
+    }
+
+}
@@ -0,0 +1,1005 @@
+use std::collections::HashMap;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+
+use libc::*;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use fuse_strato::{ReplyData, ReplyEntry, ReplyAttr, ReplyOpen, ReplyEmpty, ReplyWrite,
+                  ReplyCreate, ReplyDirectory, ReplyXattr, ReplyStatfs, ReplyLock};
+
+use tokio::prelude::*;
+use futures::future::{self, Loop};
+use futures::sync::mpsc::UnboundedSender;
+
+use crate::{Registry, ReadData};
+use crate::handler::{Handle, HandleDispatcher::*};
+use crate::controller::Request;
+use crate::link::{NodeEntry, AttrChanges};
+use crate::driver::ChannelEvent;
+use crate::file_handle::{FileHandle, FhTable, FOPEN_DIRECT_IO};
+use crate::topology::{Topology, ROOT_INO};
+use crate::permission::{self, PermissionMode};
+use crate::lock::{LockManager, LockSpace, LockKind, FileLock};
+use crate::xattr::{self, XattrMode};
+use crate::statfs::FsStats;
+use crate::dir_reply::DirectoryReply;
+use crate::stream::{self, StreamRead};
+use crate::error::DirError;
+
+/// A request, that is being handled. It resolves after the reply was sent.
+type Finish = Box<dyn Future<Item=(), Error=()> + Send>;
+
+/// A step of handling a request. It fails with the error code, that is sent as the reply.
+type Outcome<T> = Box<dyn Future<Item=T, Error=i32> + Send>;
+
+/// The number of entries, strato lists in front of the entries of a directory
+const DOT_ENTRIES: u64 = 2;
+
+
+/// This macro looks up the ino from the registry and returns the corresponding handler
+/// It sends an `ENOENT` to the FUSE driver, if the ino does not exist.
+macro_rules! get_handle {
+    ($dispatcher:ident, $ino:expr, $reply:ident) => [
+        match $dispatcher.registry.get($ino) {
+            None => {
+                $reply.error(ENOENT);
+                return Box::new(future::ok(()));
+            }
+            Some(i) => i
+        }
+    ];
+}
+
+/// This macro looks up the fh from the table of opened files.
+/// It sends an `EBADF` to the FUSE driver, if the file is not open.
+macro_rules! get_fh {
+    ($dispatcher:ident, $fh:expr, $reply:ident) => [
+        match $dispatcher.fh_table.get($fh) {
+            None => {
+                $reply.error(EBADF);
+                return Box::new(future::ok(()));
+            }
+            Some(fh) => fh
+        }
+    ];
+}
+
+/// This macro starts an operation on the node behind a handle, whatever kind it is.
+macro_rules! on_node {
+    ($handle:expr, |$node:ident| $call:expr) => [{
+        let op = match $handle.dispatch() {
+            Dir(ref $node) => $call,
+            RegularFile(ref $node) => $call,
+            Streamed(ref $node) => $call,
+            SymbolicLink(ref $node) => $call,
+            Special(ref $node) => $call,
+        };
+        let op: Outcome<_> = Box::new(op.map_err(|error| error.get_libc_code()));
+        op
+    }];
+}
+
+/// This macro starts an operation on the directory behind a handle.
+/// The operation fails with `ENOTDIR`, if the handle is not a directory.
+macro_rules! on_dir {
+    ($handle:expr, |$dir:ident| $call:expr) => [{
+        let op: Outcome<_> = match $handle.dispatch() {
+            Dir(ref $dir) => Box::new($call.map_err(|error| error.get_libc_code())),
+            _ => Box::new(future::err(ENOTDIR)),
+        };
+        op
+    }];
+}
+
+
+/// Handles the requests of the kernel on the runtime of the engine.
+/// Every request is turned into a future, that sends the reply, once it is done.
+#[derive(Clone)]
+pub(crate) struct Dispatcher {
+    registry : Registry,
+    fh_table : Arc<FhTable>,
+    topology : Arc<Topology>,
+    locks : Arc<LockManager>,
+    permissions : PermissionMode,
+    // The workers of all currently opened streams, indexed by their file handle
+    streams : Arc<Mutex<HashMap<u64, UnboundedSender<StreamRead>>>>,
+}
+
+impl Dispatcher {
+
+    pub(crate) fn new(registry: Registry, fh_table: Arc<FhTable>, topology: Arc<Topology>,
+                      locks: Arc<LockManager>, permissions: PermissionMode) -> Self {
+        Dispatcher {
+            registry,
+            fh_table,
+            topology,
+            locks,
+            permissions,
+            streams : Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Interrupts request `unique`, if it waits for a lock. Other requests run to their end.
+    pub(crate) fn interrupt(&self, unique: u64) {
+        self.locks.interrupt(unique);
+    }
+
+    pub(crate) fn dispatch(&self, event: ChannelEvent) -> Finish {
+        match event {
+            ChannelEvent::Lookup{req, parent, name, reply} =>
+                self.lookup(req, parent, name, reply),
+            ChannelEvent::GetAttr{req, ino, reply} =>
+                self.getattr(req, ino, reply),
+            ChannelEvent::SetAttr{req, ino, mode, uid, gid, size, atime, mtime, fh, reply} => {
+                let fh = fh.and_then(|fh| self.fh_table.get(fh));
+                let changes = AttrChanges::new(mode, uid, gid, size, atime, mtime, fh);
+                self.setattr(req, ino, changes, reply)
+            },
+            ChannelEvent::ReadLink{req, ino, reply} =>
+                self.readlink(req, ino, reply),
+            ChannelEvent::MkNod{req, parent, name, mode, rdev, reply} =>
+                self.mknod(req, parent, name, mode, rdev, reply),
+            ChannelEvent::MkDir{req, parent, name, mode, reply} =>
+                self.mkdir(req, parent, name, mode, reply),
+            ChannelEvent::Unlink{req, parent, name, reply} =>
+                self.unlink(req, parent, name, reply),
+            ChannelEvent::RmDir{req, parent, name, reply} =>
+                self.rmdir(req, parent, name, reply),
+            ChannelEvent::Rename{req, parent, name, new_parent, new_name, reply} =>
+                self.rename(req, parent, name, new_parent, new_name, reply),
+            ChannelEvent::SymLink{req, parent, name, target, reply} =>
+                self.symlink(req, parent, name, target, reply),
+            ChannelEvent::Link{req, ino, new_parent, new_name, reply} =>
+                self.link(req, ino, new_parent, new_name, reply),
+            ChannelEvent::Create{req, parent, name, mode, flags, reply} =>
+                self.create(req, parent, name, mode, flags, reply),
+            ChannelEvent::Open{req, ino, flags, reply} =>
+                self.open(req, ino, flags, reply),
+            ChannelEvent::Read{req, ino, fh, offset, size, reply} =>
+                self.read(req, ino, fh, offset, size, reply),
+            ChannelEvent::Write{req, ino, fh, offset, data, flags, reply} =>
+                self.write(req, ino, fh, offset, data, flags, reply),
+            ChannelEvent::Flush{req, ino, fh, lock_owner, reply} =>
+                self.flush(req, ino, fh, lock_owner, reply),
+            ChannelEvent::FSync{req, ino, fh, datasync, reply} =>
+                self.fsync(req, ino, fh, datasync, reply),
+            ChannelEvent::Release{req, ino, fh, flags, lock_owner, reply} =>
+                self.release(req, ino, fh, flags, lock_owner, reply),
+            ChannelEvent::OpenDir{req, ino, flags, reply} =>
+                self.opendir(req, ino, flags, reply),
+            ChannelEvent::ReadDir{req, ino, fh, offset, reply} =>
+                self.readdir(req, ino, fh, offset, reply),
+            ChannelEvent::ReleaseDir{req, ino, fh, reply} =>
+                self.releasedir(req, ino, fh, reply),
+            ChannelEvent::StatFs{req, ino, reply} =>
+                self.statfs(req, ino, reply),
+            ChannelEvent::SetXAttr{req, ino, name, value, flags, reply} =>
+                self.setxattr(req, ino, name, value, XattrMode::from_flags(flags), reply),
+            ChannelEvent::GetXAttr{req, ino, name, size, reply} =>
+                self.getxattr(req, ino, name, size, reply),
+            ChannelEvent::ListXAttr{req, ino, size, reply} =>
+                self.listxattr(req, ino, size, reply),
+            ChannelEvent::RemoveXAttr{req, ino, name, reply} =>
+                self.removexattr(req, ino, name, reply),
+            ChannelEvent::Access{req, ino, mask, reply} =>
+                self.access(req, ino, mask, reply),
+            ChannelEvent::GetLock{req, ino, fh, lock, reply} =>
+                self.getlk(req, ino, fh, lock, reply),
+            ChannelEvent::SetLock{req, ino, fh, lock, sleep, reply} =>
+                self.setlk(req, ino, fh, lock, sleep, reply),
+            ChannelEvent::Flock{req, ino, fh, lock, sleep, reply} =>
+                self.flock(req, ino, fh, lock, sleep, reply),
+        }
+    }
+
+
+    /// Registers the entry of a node, that was created in directory `parent`
+    fn created(&self, parent: u64, name: &str, entry: &NodeEntry) {
+        let handle = entry.get_handle();
+        let (ino, is_dir) = (handle.get_ino(), handle.is_dir());
+        self.unlinked(self.topology.created(parent, name, ino, is_dir));
+    }
+
+    /// Registers an entry, that was added to directory `parent`
+    fn linked(&self, parent: u64, name: &str, entry: &NodeEntry) {
+        self.unlinked(self.learned(parent, name, entry));
+    }
+
+    /// Registers an entry, that was found in directory `parent`.
+    /// Returns the ino of a node, that lost its last known entry by being replaced.
+    fn learned(&self, parent: u64, name: &str, entry: &NodeEntry) -> Option<u64> {
+        let handle = entry.get_handle();
+        let (ino, is_dir) = (handle.get_ino(), handle.is_dir());
+        self.topology.link(parent, name, ino, is_dir)
+    }
+
+    /// Tears down a node, if it lost its last entry
+    fn unlinked(&self, ino: Option<u64>) {
+        if let Some(ino) = ino {
+            self.topology.collect(ino, &self.registry, &self.fh_table);
+        }
+    }
+
+    /// Returns, whether the directory behind a handle handles "." and ".." itself,
+    /// or `None`, if it is not a directory
+    fn lists_dots(handle: &Handle) -> Option<bool> {
+        match handle.dispatch() {
+            Dir(ref dir) => Some(dir.lists_dots()),
+            _ => None,
+        }
+    }
+
+    /// Returns the parent of a directory. The root is its own parent.
+    fn parent_of(&self, ino: u64, handle: &Handle) -> Handle {
+        self.topology.parent(ino)
+            .and_then(|parent| self.registry.get(parent))
+            .unwrap_or_else(|| handle.clone())
+    }
+
+    /// Reads the attributes of the node behind a handle
+    fn attributes(&self, req: Request, handle: &Handle) -> Outcome<NodeEntry> {
+        let base_entry = NodeEntry::new("".to_string(), handle.clone());
+        on_node!(handle, |node| node.read_attributes(req, base_entry))
+    }
+
+    /// Checks the `R_OK`, `W_OK` and `X_OK` bits in `mask` against the attributes of the node,
+    /// if strato is responsible for checking permissions
+    fn check_access(&self, req: &Request, handle: &Handle, mask: u32) -> Outcome<()> {
+        if self.permissions != PermissionMode::Checked {
+            return Box::new(future::ok(()));
+        }
+
+        let (uid, gid) = (req.uid(), req.gid());
+        let topology = self.topology.clone();
+        Box::new(self.attributes(req.clone(), handle).and_then(move |entry| {
+            permission::check(uid, gid, &entry.to_attr(&topology), mask)
+                .map_err(|error| error.get_libc_code())
+        }))
+    }
+
+    fn reply_entry(&self, op: Outcome<NodeEntry>, reply: ReplyEntry) -> Finish {
+        let topology = self.topology.clone();
+        Box::new(op.then(move |result| {
+            match result {
+                // TODO: What does Generation do?
+                Ok(entry) => reply.entry(&entry.get_entry_ttl(),
+                                         &entry.to_attr(&topology), 0),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn reply_empty(op: Outcome<()>, reply: ReplyEmpty) -> Finish {
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(()) => reply.ok(),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+
+    fn lookup(&self, req: Request, parent: u64, name: String, reply: ReplyEntry) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let lists_dots = match Dispatcher::lists_dots(&handle) {
+            Some(lists_dots) => lists_dots,
+            None => {
+                reply.error(ENOTDIR);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        let this = self.clone();
+        let op = self.check_access(&req, &handle, X_OK as u32).and_then(move |()| {
+            if !lists_dots && (name == "." || name == "..") {
+                let target = if name == "." {
+                    handle.clone()
+                } else {
+                    this.parent_of(parent, &handle)
+                };
+                return this.attributes(req, &target);
+            }
+
+            let found = on_dir!(handle, |dir| dir.lookup(req, name.clone()));
+            let found: Outcome<NodeEntry> = Box::new(found.map(move |entry| {
+                // Learn about entries, the directory did not register itself. A replaced
+                // node is not torn down, since it may still be known under another name.
+                this.learned(parent, &name, &entry);
+                entry
+            }));
+            found
+        });
+
+        self.reply_entry(Box::new(op), reply)
+    }
+
+    fn getattr(&self, req: Request, ino: u64, reply: ReplyAttr) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let topology = self.topology.clone();
+
+        Box::new(self.attributes(req, &handle).then(move |result| {
+            match result {
+                Ok(entry) => reply.attr(&entry.get_attr_ttl(),
+                                        &entry.to_attr(&topology)),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn setattr(&self, req: Request, ino: u64, changes: AttrChanges, reply: ReplyAttr) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let this = self.clone();
+
+        let op = self.attributes(req.clone(), &handle).and_then(move |attr| {
+            if this.permissions == PermissionMode::Checked {
+                let current = attr.to_attr(&this.topology);
+                if let Err(error) = permission::check_changes(req.uid(), req.gid(),
+                                                              &current, &changes) {
+                    let denied: Outcome<NodeEntry> = Box::new(future::err(error.get_libc_code()));
+                    return denied;
+                }
+            }
+            on_node!(handle, |node| node.set_attributes(req, changes, attr))
+        });
+
+        let topology = self.topology.clone();
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(entry) => reply.attr(&entry.get_attr_ttl(),
+                                        &entry.to_attr(&topology)),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn readlink(&self, req: Request, ino: u64, reply: ReplyData) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let link_op = match handle.dispatch() {
+            SymbolicLink(ref link) => link.readlink(req),
+            _ => {
+                reply.error(EINVAL);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        Box::new(link_op.then(move |result| {
+            match result {
+                Ok(path) => reply.data(path.as_os_str().as_bytes()),
+                Err(error) => reply.error(error.get_libc_code()),
+            }
+            Ok(())
+        }))
+    }
+
+    fn mknod(&self, req: Request, parent: u64, name: String, mode: u32, rdev: u32,
+             reply: ReplyEntry) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            on_dir!(handle, |dir| dir.mknod(req, name.clone(), mode, rdev)).map(move |entry| {
+                this.created(parent, &name, &entry);
+                entry
+            })
+        });
+        self.reply_entry(Box::new(op), reply)
+    }
+
+    fn mkdir(&self, req: Request, parent: u64, name: String, mode: u32,
+             reply: ReplyEntry) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            on_dir!(handle, |dir| dir.mkdir(req, name.clone(), mode)).map(move |entry| {
+                this.created(parent, &name, &entry);
+                entry
+            })
+        });
+        self.reply_entry(Box::new(op), reply)
+    }
+
+    fn symlink(&self, req: Request, parent: u64, name: String, target: std::path::PathBuf,
+               reply: ReplyEntry) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            on_dir!(handle, |dir| dir.symlink(req, name.clone(), target)).map(move |entry| {
+                this.created(parent, &name, &entry);
+                entry
+            })
+        });
+        self.reply_entry(Box::new(op), reply)
+    }
+
+    fn link(&self, req: Request, ino: u64, new_parent: u64, new_name: String,
+            reply: ReplyEntry) -> Finish {
+        let target = get_handle!(self, ino, reply);
+        let handle = get_handle!(self, new_parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            on_dir!(handle, |dir| dir.link(req, target, new_name.clone())).map(move |entry| {
+                this.linked(new_parent, &new_name, &entry);
+                entry
+            })
+        });
+        self.reply_entry(Box::new(op), reply)
+    }
+
+    fn unlink(&self, req: Request, parent: u64, name: String, reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            on_dir!(handle, |dir| dir.unlink(req, name.clone())).map(move |()| {
+                this.unlinked(this.topology.unlink(parent, &name));
+            })
+        });
+        Dispatcher::reply_empty(Box::new(op), reply)
+    }
+
+    fn rmdir(&self, req: Request, parent: u64, name: String, reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            on_dir!(handle, |dir| dir.rmdir(req, name.clone())).map(move |()| {
+                this.unlinked(this.topology.unlink(parent, &name));
+            })
+        });
+        Dispatcher::reply_empty(Box::new(op), reply)
+    }
+
+    fn rename(&self, req: Request, parent: u64, name: String, new_parent: u64, new_name: String,
+              reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        if parent == new_parent {
+            let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+                on_dir!(handle, |dir| dir.rename(req, name.clone(), new_name.clone()))
+                    .map(move |()| {
+                        this.unlinked(this.topology.rename(parent, &name, parent, &new_name));
+                    })
+            });
+            return Dispatcher::reply_empty(Box::new(op), reply);
+        }
+
+        // Moving between directories is done in two steps, since each directory only
+        // manages its own entries.
+        let new_handle = get_handle!(self, new_parent, reply);
+        if !new_handle.is_dir() {
+            reply.error(ENOTDIR);
+            return Box::new(future::ok(()));
+        }
+
+        let checked = self.check_access(&req, &handle, (W_OK | X_OK) as u32)
+            .join(self.check_access(&req, &new_handle, (W_OK | X_OK) as u32));
+
+        let op = checked.and_then(move |_| {
+            let moved_out = match handle.dispatch() {
+                Dir(ref dir) => dir.move_out(req.clone(), name.clone()),
+                _ => return future::Either::A(future::err(ENOTDIR)),
+            };
+
+            future::Either::B(moved_out.map_err(move_error).and_then(move |entry| {
+                let moved_in: Outcome<()> = match new_handle.dispatch() {
+                    Dir(ref dir) => Box::new(
+                        dir.move_in(req.clone(), new_name.clone(), entry.clone())
+                            .map_err(move_error)),
+                    _ => Box::new(future::err(ENOTDIR)),
+                };
+
+                moved_in.then(move |result| {
+                    let done: Outcome<()> = match result {
+                        Ok(()) => {
+                            this.unlinked(this.topology.rename(parent, &name,
+                                                               new_parent, &new_name));
+                            Box::new(future::ok(()))
+                        },
+                        Err(code) => {
+                            // Hand the entry back to its old directory, so it does not get lost
+                            let restored = on_dir!(handle,
+                                                   |dir| dir.move_in(req, name.clone(), entry));
+                            Box::new(restored.then(move |restored| {
+                                match restored {
+                                    Ok(()) => Err(code),
+                                    Err(error) => {
+                                        // The entry is in neither directory now, but its node
+                                        // is kept, as it may still be in use
+                                        warn!("Failed to move {} back into directory {}, \
+                                               error {}", name, parent, error);
+                                        this.topology.lost(parent, &name);
+                                        Err(EIO)
+                                    }
+                                }
+                            }))
+                        },
+                    };
+                    done
+                })
+            }))
+        });
+        Dispatcher::reply_empty(Box::new(op), reply)
+    }
+
+    fn create(&self, req: Request, parent: u64, name: String, mode: u32, flags: u32,
+              reply: ReplyCreate) -> Finish {
+        let handle = get_handle!(self, parent, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, (W_OK | X_OK) as u32).and_then(move |()| {
+            let created = on_dir!(handle, |dir| dir.create(req.clone(), name.clone(), mode, flags));
+            created.and_then(move |entry| {
+                this.created(parent, &name, &entry);
+
+                // The new file is opened like every other file
+                let file = entry.get_handle();
+                let opened: Outcome<FileHandle> = match file.dispatch() {
+                    RegularFile(ref node) => Box::new(node.open(req, flags)
+                        .map_err(|error| error.get_libc_code())),
+                    // Only regular files can be created
+                    _ => Box::new(future::err(EINVAL)),
+                };
+
+                let ino = file.get_ino();
+                opened.map(move |fh| (entry, this.fh_table.insert(ino, fh)))
+            })
+        });
+
+        let topology = self.topology.clone();
+        Box::new(op.then(move |result| {
+            match result {
+                Ok((entry, fh)) => reply.created(&entry.get_entry_ttl(),
+                                                 &entry.to_attr(&topology), 0,
+                                                 fh.get_fh(), fh.get_open_flags()),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn open(&self, req: Request, ino: u64, flags: u32, reply: ReplyOpen) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, permission::open_mask(flags)).and_then(move |()| {
+            let opened: Outcome<(u64, u32)> = match handle.dispatch() {
+                Streamed(ref file) => {
+                    let policy = file.seek_policy();
+                    let (this, handle, stream_req) = (this.clone(), handle.clone(), req.clone());
+                    Box::new(file.open_stream(req).map_err(|error| error.get_libc_code())
+                        .map(move |file_stream| {
+                            let fh = this.fh_table.insert(ino, FileHandle::empty()).get_fh();
+                            let worker = stream::spawn_stream_worker(handle, stream_req,
+                                                                     policy, file_stream);
+                            this.streams.lock().insert(fh, worker);
+
+                            // The length of a stream is unknown, so the page cache must be
+                            // bypassed, or the kernel would stop reading at the reported size.
+                            (fh, FOPEN_DIRECT_IO)
+                        }))
+                }
+                RegularFile(ref file) => {
+                    let this = this.clone();
+                    Box::new(file.open(req, flags).map_err(|error| error.get_libc_code())
+                        .map(move |fh| {
+                            let fh = this.fh_table.insert(ino, fh);
+                            (fh.get_fh(), fh.get_open_flags())
+                        }))
+                }
+                Dir(_) => Box::new(future::err(EISDIR)),
+                // Links are resolved by the kernel, unless O_NOFOLLOW is used
+                SymbolicLink(_) => Box::new(future::err(ELOOP)),
+                // Pipes, sockets and devices are opened by the kernel itself
+                Special(_) => Box::new(future::err(ENXIO)),
+            };
+            opened
+        });
+
+        Box::new(op.then(move |result| {
+            match result {
+                Ok((fh, open_flags)) => reply.opened(fh, open_flags),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn read(&self, req: Request, ino: u64, fh: u64, offset: i64, size: u32,
+            reply: ReplyData) -> Finish {
+        if let Some(worker) = self.streams.lock().get(&fh) {
+            // If the worker is gone, the reply is dropped with the message
+            let _ = worker.unbounded_send(StreamRead{offset, size, reply});
+            return Box::new(future::ok(()));
+        }
+
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let file_op = match handle.dispatch() {
+            RegularFile(ref file) => {
+                file.read(req, fh, offset, size)
+            }
+            Streamed(_) => {
+                reply.error(EBADF);
+                return Box::new(future::ok(()));
+            }
+            _ => {
+                reply.error(EISDIR);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        Box::new(file_op.then(move |result| {
+            match result {
+                Ok(ReadData::Bytes(mut vec)) => {
+                    // The kernel treats any reply shorter than `size` as a short
+                    // read or EOF, but never accepts more data than requested.
+                    vec.truncate(size as usize);
+                    reply.data(vec);
+                }
+                Ok(ReadData::Splice{fd, offset}) => {
+                    reply.splice(fd, offset, size);
+                }
+                Err(error) => {
+                    reply.error(error.get_libc_code());
+                }
+            }
+            Ok(())
+        }))
+    }
+
+    // Takes the arguments of `Filesystem::write` along
+    #[allow(clippy::too_many_arguments)]
+    fn write(&self, req: Request, ino: u64, fh: u64, offset: i64, data: Bytes, flags: u32,
+             reply: ReplyWrite) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let file_op = match handle.dispatch() {
+            RegularFile(ref file) => {
+                file.write(req, fh, offset, data, flags)
+            }
+            Streamed(_) => {
+                reply.error(EBADF);
+                return Box::new(future::ok(()));
+            }
+            _ => {
+                reply.error(EISDIR);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        Box::new(file_op.then(move |result| {
+            match result {
+                Ok(written) => reply.written(written),
+                Err(error) => reply.error(error.get_libc_code()),
+            }
+            Ok(())
+        }))
+    }
+
+    fn flush(&self, req: Request, ino: u64, fh: u64, lock_owner: u64,
+             reply: ReplyEmpty) -> Finish {
+        // POSIX locks are dropped, whenever the owner closes the file
+        self.locks.release_owner(ino, LockSpace::Record, lock_owner);
+
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let file_op: Outcome<()> = match handle.dispatch() {
+            RegularFile(ref file) => {
+                Box::new(file.flush(req, fh, lock_owner).map_err(|error| error.get_libc_code()))
+            }
+            _ => Box::new(future::ok(())),
+        };
+        Dispatcher::reply_empty(file_op, reply)
+    }
+
+    fn fsync(&self, req: Request, ino: u64, fh: u64, datasync: bool,
+             reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let file_op: Outcome<()> = match handle.dispatch() {
+            RegularFile(ref file) => {
+                Box::new(file.fsync(req, fh, datasync).map_err(|error| error.get_libc_code()))
+            }
+            _ => Box::new(future::ok(())),
+        };
+        Dispatcher::reply_empty(file_op, reply)
+    }
+
+    fn release(&self, req: Request, ino: u64, fh: u64, flags: u32, lock_owner: u64,
+               reply: ReplyEmpty) -> Finish {
+        // Dropping the sender ends the worker and with it the stream
+        self.streams.lock().remove(&fh);
+        self.locks.release_owner(ino, LockSpace::Record, lock_owner);
+        self.locks.release_owner(ino, LockSpace::Flock, fh);
+
+        // The handle is dropped in any case, even if the file does not exist anymore
+        let fh = match self.fh_table.remove(fh) {
+            Some(fh) => fh,
+            None => {
+                reply.ok();
+                return Box::new(future::ok(()));
+            }
+        };
+
+        let handle = get_handle!(self, ino, reply);
+        let file_op: Outcome<()> = match handle.dispatch() {
+            RegularFile(ref file) => {
+                Box::new(file.release(req, fh, flags).map_err(|error| error.get_libc_code()))
+            }
+            _ => Box::new(future::ok(())),
+        };
+
+        let this = self.clone();
+        Dispatcher::reply_empty(Box::new(file_op.then(move |result| {
+            // An unlinked file is torn down, after it was closed for the last time
+            this.topology.released(ino, &this.registry, &this.fh_table);
+            result
+        })), reply)
+    }
+
+    fn opendir(&self, req: Request, ino: u64, flags: u32, reply: ReplyOpen) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let this = self.clone();
+
+        let op = self.check_access(&req, &handle, permission::open_mask(flags)).and_then(move |()| {
+            on_dir!(handle, |dir| dir.opendir(req, flags))
+                .map(move |fh| this.fh_table.insert(ino, fh))
+        });
+
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(fh) => reply.opened(fh.get_fh(), fh.get_open_flags()),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn readdir(&self, req: Request, ino: u64, fh: u64, offset: i64,
+               reply: ReplyDirectory) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let lists_dots = match Dispatcher::lists_dots(&handle) {
+            Some(lists_dots) => lists_dots,
+            None => {
+                reply.error(ENOTDIR);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        let offset = offset as u64;
+        let reserved = if lists_dots { 0 } else { DOT_ENTRIES };
+        let mut dir_reply = DirectoryReply::new(reserved, reply.size());
+
+        // "." and ".." are listed at the offsets 1 and 2, before the real entries
+        if !lists_dots {
+            let dots = [(1, ".", handle.clone()), (2, "..", self.parent_of(ino, &handle))];
+            for (dot_offset, name, target) in dots.iter().filter(|dot| dot.0 > offset) {
+                let entry = NodeEntry::new(name.to_string(), target.clone());
+                if dir_reply.add_reserved(&entry, *dot_offset) {
+                    break;
+                }
+            }
+        }
+
+        let op: Outcome<DirectoryReply> = if dir_reply.is_full() {
+            Box::new(future::ok(dir_reply))
+        } else {
+            // The offset is the cookie of the last entry, the kernel received
+            let cookie = dir_reply.to_cookie(offset);
+            on_dir!(handle, |dir| dir.readdir(req, fh, cookie, dir_reply))
+        };
+
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(dir_reply) => dir_reply.send(reply),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn releasedir(&self, req: Request, ino: u64, fh: u64, reply: ReplyEmpty) -> Finish {
+        // The handle is dropped in any case, even if the directory does not exist anymore
+        let fh = self.fh_table.remove(fh);
+        let handle = get_handle!(self, ino, reply);
+
+        let dir_op: Outcome<()> = match (handle.dispatch(), fh) {
+            (Dir(ref dir), Some(fh)) => {
+                Box::new(dir.releasedir(req, fh).map_err(|error| error.get_libc_code()))
+            },
+            _ => Box::new(future::ok(())),
+        };
+
+        let this = self.clone();
+        Dispatcher::reply_empty(Box::new(dir_op.then(move |result| {
+            this.topology.released(ino, &this.registry, &this.fh_table);
+            result
+        })), reply)
+    }
+
+    fn statfs(&self, req: Request, ino: u64, reply: ReplyStatfs) -> Finish {
+        // Statistics of nodes, that are no directories, are asked of the root
+        let handle = self.registry.get(ino)
+            .filter(|handle| handle.is_dir())
+            .or_else(|| self.registry.get(ROOT_INO));
+        let used_files = self.registry.len() as u64;
+
+        let handle = match handle {
+            Some(handle) => handle,
+            None => {
+                reply.error(ENOENT);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        let op = on_dir!(handle, |dir| dir.statfs(req, FsStats::new(used_files)));
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(stats) => reply.statfs(stats.blocks, stats.blocks_free, stats.blocks_available,
+                                          stats.files, stats.files_free, stats.block_size,
+                                          stats.name_length, stats.fragment_size),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn setxattr(&self, req: Request, ino: u64, name: String, value: Vec<u8>, mode: XattrMode,
+                reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+
+        let op = self.check_access(&req, &handle, W_OK as u32).and_then(move |()| {
+            on_node!(handle, |node| node.set_xattr(req, name, value, mode))
+        });
+        Dispatcher::reply_empty(Box::new(op), reply)
+    }
+
+    fn getxattr(&self, req: Request, ino: u64, name: String, size: u32,
+                reply: ReplyXattr) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+
+        let op = self.check_access(&req, &handle, R_OK as u32).and_then(move |()| {
+            on_node!(handle, |node| node.get_xattr(req, name))
+        });
+
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(value) => xattr::reply_sized(reply, size, &value),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn listxattr(&self, req: Request, ino: u64, size: u32, reply: ReplyXattr) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+
+        let op = self.check_access(&req, &handle, R_OK as u32).and_then(move |()| {
+            on_node!(handle, |node| node.list_xattr(req))
+        });
+
+        Box::new(op.then(move |result| {
+            match result {
+                Ok(names) => xattr::reply_sized(reply, size, &xattr::encode_list(names)),
+                Err(code) => reply.error(code),
+            }
+            Ok(())
+        }))
+    }
+
+    fn removexattr(&self, req: Request, ino: u64, name: String, reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+
+        let op = self.check_access(&req, &handle, W_OK as u32).and_then(move |()| {
+            on_node!(handle, |node| node.remove_xattr(req, name))
+        });
+        Dispatcher::reply_empty(Box::new(op), reply)
+    }
+
+    fn access(&self, req: Request, ino: u64, mask: u32, reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        Dispatcher::reply_empty(self.check_access(&req, &handle, mask), reply)
+    }
+
+    fn getlk(&self, req: Request, ino: u64, fh: u64, lock: FileLock, reply: ReplyLock) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let file_op = match handle.dispatch() {
+            RegularFile(ref file) if file.manages_locks() => {
+                file.get_lock(req, fh, lock.clone())
+            }
+            _ => {
+                match self.locks.test(ino, LockSpace::Record, &lock) {
+                    Some(held) => reply.locked(held.start, held.end,
+                                               held.kind.to_type(), held.pid),
+                    None => reply.locked(lock.start, lock.end,
+                                         LockKind::Unlock.to_type(), lock.pid),
+                }
+                return Box::new(future::ok(()));
+            }
+        };
+
+        Box::new(file_op.then(move |result| {
+            match result {
+                Ok(Some(held)) => reply.locked(held.start, held.end,
+                                               held.kind.to_type(), held.pid),
+                Ok(None) => reply.locked(lock.start, lock.end,
+                                         LockKind::Unlock.to_type(), lock.pid),
+                Err(error) => reply.error(error.get_libc_code()),
+            }
+            Ok(())
+        }))
+    }
+
+    fn setlk(&self, req: Request, ino: u64, fh: u64, lock: FileLock, sleep: bool,
+             reply: ReplyEmpty) -> Finish {
+        let handle = get_handle!(self, ino, reply);
+        let fh = get_fh!(self, fh, reply);
+        let unique = req.id();
+        let file_op: Outcome<()> = match handle.dispatch() {
+            RegularFile(ref file) if file.manages_locks() => {
+                Box::new(file.set_lock(req, fh, lock, sleep)
+                    .map_err(|error| error.get_libc_code()))
+            }
+            _ => self.set_lock(ino, LockSpace::Record, lock, sleep, unique),
+        };
+        Dispatcher::reply_empty(file_op, reply)
+    }
+
+    fn flock(&self, req: Request, ino: u64, fh: u64, lock: FileLock, sleep: bool,
+             reply: ReplyEmpty) -> Finish {
+        get_fh!(self, fh, reply);
+        let file_op = self.set_lock(ino, LockSpace::Flock, lock, sleep, req.id());
+        Dispatcher::reply_empty(file_op, reply)
+    }
+
+    /// Sets a lock kept by strato. If `sleep` is set, this waits until the lock is acquired,
+    /// instead of failing with `EAGAIN`. The wait fails with `EDEADLK`, if it never ends.
+    fn set_lock(&self, ino: u64, space: LockSpace, lock: FileLock, sleep: bool, unique: u64)
+        -> Outcome<()> {
+        if !sleep {
+            return Box::new(future::result(self.locks.set(ino, space, lock)));
+        }
+
+        // Retry every time the locks of the node change, until the lock
+        // is acquired or the wait is interrupted
+        let locks = self.locks.clone();
+        Box::new(future::loop_fn((), move |()| {
+            let next: Outcome<Loop<(), ()>>
+            = match locks.set_or_wait(ino, space, lock.clone(), unique) {
+                Ok(None) => Box::new(future::ok(Loop::Break(()))),
+                Ok(Some(changed)) => Box::new(changed.then(|woken| {
+                    match woken {
+                        Ok(true) => Ok(Loop::Continue(())),
+                        _ => Err(EINTR),
+                    }
+                })),
+                Err(code) => Box::new(future::err(code)),
+            };
+            next
+        }))
+    }
+
+}
+
+/// Tells the caller of a move between directories to fall back to copying,
+/// if one of the directories does not support moves
+fn move_error(error: DirError) -> i32 {
+    if error.is_not_implemented() {
+        EXDEV
+    } else {
+        error.get_libc_code()
+    }
+}
@@ -1,181 +1,469 @@
-use std::sync::Arc;
 use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use libc::*;
 
-use fuse::{Filesystem, ReplyDirectory, ReplyData, ReplyEntry, ReplyAttr};
-use fuse::Request as FuseRequest;
+use fuse_strato::{Filesystem, ReplyDirectory, ReplyData, ReplyEntry, ReplyAttr, ReplyOpen,
+                  ReplyEmpty, ReplyWrite, ReplyCreate, ReplyXattr, ReplyStatfs, ReplyLock};
+use fuse_strato::Request as FuseRequest;
 
-use futures::sync::mpsc::UnboundedSender;
-
-use crate::handler::HandleDispatcher::*;
-use crate::utils::InoGenerator;
-use crate::link::NodeEntry;
-use crate::controller::Request;
-use crate::Registry;
+use bytes::Bytes;
+use time::Timespec;
 
+use tokio::runtime::TaskExecutor;
 
-/// This macro looks up the ino from the registry and returns the corresponding handler
-/// It sends an `ENOENT` to the FUSE driver, if the ino does not exist.
-macro_rules! get_handle {
-    ($driver:ident, $ino: ident, $reply:ident) => [
-        match $driver.registry.read().get(&$ino) {
-            None => {
-                $reply.error(ENOENT);
-                return;
-            }
-            Some(i) => i
-        }.clone()
-    ];
-}
+use crate::controller::Request;
+use crate::dispatch::Dispatcher;
+use crate::lock::{FileLock, LockKind};
+use crate::utils::to_timespec;
 
 
+/// Receives the requests of the FUSE session on its worker threads and spawns them onto the
+/// runtime of the engine, where they are handled by the `Dispatcher`.
 pub(crate) struct Driver {
-    registry : Registry,
-    channel : UnboundedSender<ChannelEvent>
-    //ino_generator : Arc<InoGenerator>,
+    dispatcher : Dispatcher,
+    executor : TaskExecutor,
 }
 
 impl Driver {
 
-    pub(crate) fn new(registry: Registry, _ino_generator : Arc<InoGenerator>,
-    channel : UnboundedSender<ChannelEvent>) -> Self {
+    pub(crate) fn new(dispatcher : Dispatcher, executor : TaskExecutor) -> Self {
         Driver {
-            registry : registry.clone(),
-            channel,
-            //ino_generator : ino_generator.clone(),
+            dispatcher,
+            executor,
         }
     }
 
+    fn dispatch(&self, event: ChannelEvent) {
+        self.executor.spawn(self.dispatcher.dispatch(event));
+    }
+
 }
 
 
-// TODO: Implement macros to check if directory or file with appropriate errors
 impl Filesystem for Driver {
 
-    fn lookup(&mut self, req: &FuseRequest, parent: u64, name: &OsStr, reply: ReplyEntry) {
+    fn lookup(&self, request: &FuseRequest, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Lookup {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            reply
+        });
+    }
 
-        let handle = get_handle!(self, parent, reply);
-        let request = Request::new(req);
-        let n = name.to_string_lossy().to_string();
+    fn getattr(&self, request: &FuseRequest, ino: u64, reply: ReplyAttr) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::GetAttr {
+            req,
+            ino,
+            reply
+        });
+    }
 
-        let result = match handle.write().dispatch() {
-            Dir(ref mut dir) => {
-                dir.lookup(request, n)
-            }
-            _ => {
-                reply.error(ENOTDIR);
-                return;
-            }
-        };
+    fn setattr(&self, request: &FuseRequest, ino: u64, mode: Option<u32>, uid: Option<u32>,
+               gid: Option<u32>, size: Option<u64>, atime: Option<SystemTime>,
+               mtime: Option<SystemTime>, fh: Option<u64>, reply: ReplyAttr) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::SetAttr {
+            req,
+            ino,
+            mode,
+            uid,
+            gid,
+            size,
+            atime: atime.map(to_timespec),
+            mtime: mtime.map(to_timespec),
+            fh,
+            reply
+        });
+    }
 
-        match result {
-            Ok(entry) => {
-                // TODO: What does Generation do?
-                reply.entry(&entry.get_ttl(), &entry.to_attr(), 0);
-            },
-            Err(error) => { reply.error(error.get_libc_code()); }
-        }
+    fn readlink(&self, request: &FuseRequest, ino: u64, reply: ReplyData) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::ReadLink {
+            req,
+            ino,
+            reply
+        });
+    }
 
+    fn mknod(&self, request: &FuseRequest, parent: u64, name: &OsStr, mode: u32, rdev: u32,
+             reply: ReplyEntry) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::MkNod {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            mode,
+            rdev,
+            reply
+        });
     }
 
-    fn getattr(&mut self, req: &FuseRequest, ino: u64, reply: ReplyAttr) {
+    fn mkdir(&self, request: &FuseRequest, parent: u64, name: &OsStr, mode: u32,
+             reply: ReplyEntry) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::MkDir {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            mode,
+            reply
+        });
+    }
 
-        let handle = get_handle!(self, ino, reply);
-        let request = Request::new(req);
-        let base_entry = NodeEntry::new("".to_string(), handle.clone());
+    fn unlink(&self, request: &FuseRequest, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Unlink {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            reply
+        });
+    }
 
-        let result = match handle.write().dispatch() {
-            Dir(ref mut dir) => {
-                dir.read_attributes(request, base_entry)
-            }
-            RegularFile(ref mut file) => {
-                file.read_attributes(request, base_entry)
-            }
-        };
+    fn rmdir(&self, request: &FuseRequest, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::RmDir {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            reply
+        });
+    }
 
-        match result {
-            Ok(entry) => reply.attr(&entry.get_ttl(), &entry.to_attr()),
-            Err(error) => reply.error(error.get_libc_code()),
-        }
+    fn rename(&self, request: &FuseRequest, parent: u64, name: &OsStr, newparent: u64,
+              newname: &OsStr, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Rename {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            new_parent: newparent,
+            new_name: newname.to_string_lossy().to_string(),
+            reply
+        });
+    }
+
+    fn symlink(&self, request: &FuseRequest, parent: u64, name: &OsStr, link: &Path,
+               reply: ReplyEntry) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::SymLink {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            target: link.to_path_buf(),
+            reply
+        });
+    }
+
+    fn link(&self, request: &FuseRequest, ino: u64, newparent: u64, newname: &OsStr,
+            reply: ReplyEntry) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Link {
+            req,
+            ino,
+            new_parent: newparent,
+            new_name: newname.to_string_lossy().to_string(),
+            reply
+        });
+    }
 
+    fn create(&self, request: &FuseRequest, parent: u64, name: &OsStr, mode: u32, flags: u32,
+              reply: ReplyCreate) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Create {
+            req,
+            parent,
+            name: name.to_string_lossy().to_string(),
+            mode,
+            flags,
+            reply
+        });
     }
 
-    // TODO: Implement correct behaviour of offset and size... how to handle streaming?
-    fn read(&mut self, request: &FuseRequest, ino: u64, fh: u64,
+    fn open(&self, request: &FuseRequest, ino: u64, flags: u32, reply: ReplyOpen) {
+        let req = Request::new(request);
+
+        self.dispatch(ChannelEvent::Open {
+            req,
+            ino,
+            flags,
+            reply
+        });
+    }
+
+    fn release(&self, request: &FuseRequest, ino: u64, fh: u64, flags: u32,
+               lock_owner: u64, _flush: bool, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Release {
+            req,
+            ino,
+            fh,
+            flags,
+            lock_owner,
+            reply
+        });
+    }
+
+    fn read(&self, request: &FuseRequest, ino: u64, fh: u64,
             offset: i64, size: u32, reply: ReplyData) {
         let req = Request::new(request);
-        self.channel.unbounded_send(ChannelEvent::Read {
+        self.dispatch(ChannelEvent::Read {
             req,
             ino,
             fh,
             offset,
             size,
             reply
-        }).unwrap();
-//        let handle = get_handle!(self, ino, reply);
-//        let result = match handle.write().dispatch() {
-//            RegularFile(ref mut file) => {
-//                file.read(req)
-//            }
-//            _ => {
-//                reply.error(EISDIR);
-//            return;
-//            }
-//        };
-//
-//        match result {
-//            Ok(vec) => {
-//                println!("Request params: Offset {} Size {}", offset, size);
-//                reply.data(&vec[offset as usize..]);
-//            }
-//            Err(error) => {
-//                reply.error(error.get_libc_code());
-//            }
-//        }
-    }
-
-    fn readdir(&mut self, req: &FuseRequest, ino: u64, _fh: u64,
-               offset: i64, mut reply: ReplyDirectory) {
-
-        let handle = get_handle!(self, ino, reply);
-        let request = Request::new(req);
-        // Check that the handle references a directory
-        let result = match handle.write().dispatch() {
-            // Check that this is actually a directory
-            Dir(ref mut dir) => {
-                dir.readdir(request)
-            },
-            _ => {
-                reply.error(ENOTDIR);
+        });
+    }
+
+    fn write(&self, request: &FuseRequest, ino: u64, fh: u64, offset: i64,
+             data: Bytes, flags: u32, reply: ReplyWrite) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Write {
+            req,
+            ino,
+            fh,
+            offset,
+            data,
+            flags,
+            reply
+        });
+    }
+
+    fn flush(&self, request: &FuseRequest, ino: u64, fh: u64, lock_owner: u64,
+             reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Flush {
+            req,
+            ino,
+            fh,
+            lock_owner,
+            reply
+        });
+    }
+
+    fn fsync(&self, request: &FuseRequest, ino: u64, fh: u64, datasync: bool,
+             reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::FSync {
+            req,
+            ino,
+            fh,
+            datasync,
+            reply
+        });
+    }
+
+    fn getlk(&self, request: &FuseRequest, ino: u64, fh: u64, lock_owner: u64, start: u64,
+             end: u64, typ: u32, pid: u32, reply: ReplyLock) {
+        let req = Request::new(request);
+        let kind = match LockKind::from_type(typ) {
+            Some(kind) => kind,
+            None => {
+                reply.error(EINVAL);
                 return;
-            },
+            }
         };
 
-        match result {
-            Ok(vec) => {
-                let to_skip = if offset == 0 { offset } else { offset + 1 } as usize;
-                for (i, entry) in vec.into_iter().enumerate().skip(to_skip) {
+        self.dispatch(ChannelEvent::GetLock {
+            req,
+            ino,
+            fh,
+            lock: FileLock { start, end, kind, pid, owner: lock_owner },
+            reply
+        });
+    }
 
-                    let rep = entry.to_reply();
-                    reply.add(rep.0, i as i64,rep.1, rep.2);
+    fn setlk(&self, request: &FuseRequest, ino: u64, fh: u64, lock_owner: u64, start: u64,
+             end: u64, typ: u32, pid: u32, sleep: bool, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        let kind = match LockKind::from_type(typ) {
+            Some(kind) => kind,
+            None => {
+                reply.error(EINVAL);
+                return;
+            }
+        };
 
-                }
-                reply.ok();
-            },
-            Err(error) => {
-                reply.error(error.get_libc_code());
+        // Waiting for a lock happens on the runtime, so it does not block other requests
+        self.dispatch(ChannelEvent::SetLock {
+            req,
+            ino,
+            fh,
+            lock: FileLock { start, end, kind, pid, owner: lock_owner },
+            sleep,
+            reply
+        });
+    }
+
+    fn flock(&self, request: &FuseRequest, ino: u64, fh: u64, typ: u32, pid: u32, sleep: bool,
+             reply: ReplyEmpty) {
+        let req = Request::new(request);
+        let kind = match LockKind::from_type(typ) {
+            Some(kind) => kind,
+            None => {
+                reply.error(EINVAL);
+                return;
             }
-        }
+        };
+
+        // The lock belongs to the open file, so it is shared by duplicated descriptors
+        self.dispatch(ChannelEvent::Flock {
+            req,
+            ino,
+            fh,
+            lock: FileLock { start: 0, end: u64::MAX, kind, pid, owner: fh },
+            sleep,
+            reply
+        });
+    }
+
+    fn interrupt(&self, _request: &FuseRequest, unique: u64) {
+        // Only waits for locks are interrupted, which must not wait behind other requests
+        self.dispatcher.interrupt(unique);
+    }
+
+    fn opendir(&self, request: &FuseRequest, ino: u64, flags: u32, reply: ReplyOpen) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::OpenDir {
+            req,
+            ino,
+            flags,
+            reply
+        });
+    }
+
+    fn readdir(&self, request: &FuseRequest, ino: u64, fh: u64,
+               offset: i64, reply: ReplyDirectory) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::ReadDir {
+            req,
+            ino,
+            fh,
+            offset,
+            reply
+        });
+    }
+
+    fn statfs(&self, request: &FuseRequest, ino: u64, reply: ReplyStatfs) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::StatFs {
+            req,
+            ino,
+            reply
+        });
+    }
+
+    fn setxattr(&self, request: &FuseRequest, ino: u64, name: &OsStr, value: &[u8],
+                flags: u32, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::SetXAttr {
+            req,
+            ino,
+            name: name.to_string_lossy().to_string(),
+            value: value.to_vec(),
+            flags,
+            reply
+        });
+    }
+
+    fn getxattr(&self, request: &FuseRequest, ino: u64, name: &OsStr, size: u32,
+                reply: ReplyXattr) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::GetXAttr {
+            req,
+            ino,
+            name: name.to_string_lossy().to_string(),
+            size,
+            reply
+        });
+    }
+
+    fn listxattr(&self, request: &FuseRequest, ino: u64, size: u32, reply: ReplyXattr) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::ListXAttr {
+            req,
+            ino,
+            size,
+            reply
+        });
+    }
+
+    fn removexattr(&self, request: &FuseRequest, ino: u64, name: &OsStr,
+                   reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::RemoveXAttr {
+            req,
+            ino,
+            name: name.to_string_lossy().to_string(),
+            reply
+        });
+    }
+
+    fn access(&self, request: &FuseRequest, ino: u64, mask: u32, reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::Access {
+            req,
+            ino,
+            mask,
+            reply
+        });
+    }
+
+    fn releasedir(&self, request: &FuseRequest, ino: u64, fh: u64, _flags: u32,
+                  reply: ReplyEmpty) {
+        let req = Request::new(request);
+        self.dispatch(ChannelEvent::ReleaseDir {
+            req,
+            ino,
+            fh,
+            reply
+        });
     }
 
 }
 
 #[derive(Debug)]
 pub(crate) enum ChannelEvent {
+    Lookup{req: Request, parent: u64, name: String, reply: ReplyEntry},
+    GetAttr{req: Request, ino: u64, reply: ReplyAttr},
+    SetAttr{req: Request, ino: u64, mode: Option<u32>, uid: Option<u32>, gid: Option<u32>,
+        size: Option<u64>, atime: Option<Timespec>, mtime: Option<Timespec>, fh: Option<u64>,
+        reply: ReplyAttr},
+    ReadLink{req: Request, ino: u64, reply: ReplyData},
+    MkNod{req: Request, parent: u64, name: String, mode: u32, rdev: u32, reply: ReplyEntry},
+    MkDir{req: Request, parent: u64, name: String, mode: u32, reply: ReplyEntry},
+    Unlink{req: Request, parent: u64, name: String, reply: ReplyEmpty},
+    RmDir{req: Request, parent: u64, name: String, reply: ReplyEmpty},
+    Rename{req: Request, parent: u64, name: String, new_parent: u64, new_name: String,
+        reply: ReplyEmpty},
+    SymLink{req: Request, parent: u64, name: String, target: PathBuf, reply: ReplyEntry},
+    Link{req: Request, ino: u64, new_parent: u64, new_name: String, reply: ReplyEntry},
+    Create{req: Request, parent: u64, name: String, mode: u32, flags: u32, reply: ReplyCreate},
+    Open{req: Request, ino: u64, flags: u32, reply: ReplyOpen},
     Read{req: Request, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData},
+    Write{req: Request, ino: u64, fh: u64, offset: i64, data: Bytes, flags: u32,
+        reply: ReplyWrite},
+    Flush{req: Request, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty},
+    FSync{req: Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty},
+    Release{req: Request, ino: u64, fh: u64, flags: u32, lock_owner: u64, reply: ReplyEmpty},
+    GetLock{req: Request, ino: u64, fh: u64, lock: FileLock, reply: ReplyLock},
+    SetLock{req: Request, ino: u64, fh: u64, lock: FileLock, sleep: bool, reply: ReplyEmpty},
+    Flock{req: Request, ino: u64, fh: u64, lock: FileLock, sleep: bool, reply: ReplyEmpty},
+    OpenDir{req: Request, ino: u64, flags: u32, reply: ReplyOpen},
+    ReadDir{req: Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory},
+    ReleaseDir{req: Request, ino: u64, fh: u64, reply: ReplyEmpty},
+    StatFs{req: Request, ino: u64, reply: ReplyStatfs},
+    SetXAttr{req: Request, ino: u64, name: String, value: Vec<u8>, flags: u32,
+        reply: ReplyEmpty},
+    GetXAttr{req: Request, ino: u64, name: String, size: u32, reply: ReplyXattr},
+    ListXAttr{req: Request, ino: u64, size: u32, reply: ReplyXattr},
+    RemoveXAttr{req: Request, ino: u64, name: String, reply: ReplyEmpty},
+    Access{req: Request, ino: u64, mask: u32, reply: ReplyEmpty},
 }
-
-
-
-
@@ -1,57 +1,63 @@
 use std::sync::Arc;
-use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::io;
-use std::thread;
+use std::ffi::OsStr;
 
-use parking_lot::RwLock;
-
-use fuse::BackgroundSession;
-
-use libc::*;
+use fuse_strato::{Session, Config};
 
 use tokio::prelude::*;
-use futures::sync::mpsc::{UnboundedSender, UnboundedReceiver};
+use tokio::runtime::Runtime;
 
-use crate::{File, Directory, Registry};
+use crate::{File, Directory, StreamFile, Symlink, SpecialFile, Registry};
 use crate::handler::{Handle, HandleDispatcher::*};
 use crate::controller::Controller;
-use crate::driver::{Driver, ChannelEvent};
+use crate::driver::Driver;
+use crate::dispatch::Dispatcher;
 use crate::utils::InoGenerator;
+use crate::inode_table::InodeTable;
+use crate::file_handle::FhTable;
+use crate::topology::Topology;
+use crate::permission::PermissionMode;
+use crate::error::NodeError;
+use crate::lock::LockManager;
 
-macro_rules! get_handle {
-    ($registry: ident, $ino: ident, $reply:ident) => [
-        match $registry.read().get(&$ino) {
-            None => {
-                $reply.error(ENOENT);
-                return future::ok(());
-            }
-            Some(i) => i
-        }.clone()
-    ];
-}
+
+/// The number of threads, that read requests from the kernel, unless set otherwise
+const DEFAULT_WORKERS: usize = 4;
 
 
 #[derive(Debug)]
-pub struct Engine<'a> {
+pub struct Engine {
     mount_point : PathBuf,
     registry : Registry,
     ino_generator : Arc<InoGenerator>,
-    event_channel : Option<UnboundedSender<ChannelEvent>>,
-    fuse_session : Option<BackgroundSession<'a>>,
+    fh_table : Arc<FhTable>,
+    topology : Arc<Topology>,
+    locks : Arc<LockManager>,
+    permissions : PermissionMode,
+    config : Config,
+    // The session is dropped first, so that the file system is unmounted before the runtime
+    // shuts down
+    fuse_session : Option<Session>,
+    runtime : Option<Runtime>,
 }
 
-impl<'a> Engine<'a> {
+impl Engine {
 
     pub fn new<T: 'static>(path: &Path, root: T) -> Self
     where T: Directory + Send + Sync {
 
         let mut engine = Engine{
             mount_point : path.to_path_buf(),
-            registry : Arc::new(RwLock::new(BTreeMap::new())),
+            registry : Arc::new(InodeTable::new()),
             ino_generator : Arc::new(InoGenerator::new()),
-            event_channel : None,
+            fh_table : Arc::new(FhTable::new()),
+            topology : Arc::new(Topology::new()),
+            locks : Arc::new(LockManager::new()),
+            permissions : PermissionMode::default(),
+            config : Config { workers: DEFAULT_WORKERS, ..Config::default() },
             fuse_session : None,
+            runtime : None,
         };
 
         engine.add_directory(root);
@@ -59,23 +65,82 @@ impl<'a> Engine<'a> {
     }
 
 
+    /// Selects, who checks the permissions of requests. Defaults to `PermissionMode::Unchecked`.
+    /// Must be set before the engine is started.
+    pub fn permission_mode(&mut self, mode: PermissionMode) -> &mut Self {
+        self.permissions = mode;
+        self
+    }
+
+    /// Sets the number of threads, that read requests from the kernel. Every thread reads from
+    /// a queue of its own, so requests, e.g. lookups in large directory trees, are taken on in
+    /// parallel. Defaults to 4. Must be set before the engine is started.
+    pub fn workers(&mut self, workers: usize) -> &mut Self {
+        self.config.workers = workers.max(1);
+        self
+    }
+
+    /// Splices the data of `ReadData::Splice` from its file into the kernel, instead of reading
+    /// it into a buffer. A reply has to fit into a pipe, so reads are limited to
+    /// `/proc/sys/fs/pipe-max-size` less 8KB. Defaults to false. Must be set before the engine
+    /// is started.
+    pub fn splice_write(&mut self, splice: bool) -> &mut Self {
+        self.config.splice_write = splice;
+        self
+    }
+
+    /// Lets the kernel move the pages of spliced data instead of copying them.
+    /// Defaults to false. Must be set before the engine is started.
+    pub fn splice_move(&mut self, splice: bool) -> &mut Self {
+        self.config.splice_move = splice;
+        self
+    }
+
+    /// Sets the largest amount of data, that a single write passes to `File::write`.
+    /// Every worker holds a buffer of this size. Defaults to 1MB. Kernels before Linux 4.20
+    /// do not write more than 128KB at once. Must be set before the engine is started.
+    pub fn max_write(&mut self, size: u32) -> &mut Self {
+        self.config.max_write = size;
+        self
+    }
+
+    /// Sets the largest amount of data, that a single read requests from `File::read`.
+    /// Defaults to 1MB. Kernels before Linux 4.20 do not read more than 128KB at once.
+    /// Must be set before the engine is started.
+    pub fn max_read(&mut self, size: u32) -> &mut Self {
+        self.config.max_read = size;
+        self
+    }
+
+    /// Sets, how far the kernel reads ahead of sequential reads. The kernel does not read
+    /// further ahead than its own limit, which is 128KB by default. Defaults to 1MB.
+    /// Must be set before the engine is started.
+    pub fn max_readahead(&mut self, size: u32) -> &mut Self {
+        self.config.max_readahead = size;
+        self
+    }
+
     pub fn start(&mut self) -> io::Result<()> {
 
         // TODO: Find a way to use options appropriately
         //let options = vec![OsStr::new("-o"), OsStr::new("fsname=test")];
         let mount_point = self.mount_point.clone();
-        let options = [];
+        let options = match self.permissions {
+            PermissionMode::Kernel => vec![OsStr::new("-o"), OsStr::new("default_permissions")],
+            _ => vec![],
+        };
 
-        let (sender, receiver) = futures::sync::mpsc::unbounded::<ChannelEvent>();
+        // The requests are handed from the workers of the session to the runtime
+        let runtime = Runtime::new()?;
+        let dispatcher = Dispatcher::new(self.registry.clone(), self.fh_table.clone(),
+                                         self.topology.clone(), self.locks.clone(),
+                                         self.permissions);
+        let driver = Driver::new(dispatcher, runtime.executor());
 
-        let driver = Driver::new(self.registry.clone(), self.ino_generator.clone(), sender.clone());
-        let session = unsafe {fuse::spawn_mount(driver, &mount_point, &options[..])}?;
+        let session = Session::spawn(driver, &mount_point, &options[..], &self.config)?;
 
-        // TODO: Give Fuse Session to tokio session? This way we can end tokio session and automatically end Fuse session
-        self.event_channel = Some(sender);
         self.fuse_session = Some(session);
-
-        Engine::start_tokio_runtime(self.registry.clone(), receiver);
+        self.runtime = Some(runtime);
 
         Ok(())
     }
@@ -88,10 +153,10 @@ impl<'a> Engine<'a> {
         let ino = self.ino_generator.generate();
         let handle = Handle::new_file(ino, boxed);
 
-        self.registry.write().insert(ino, handle.clone());
+        self.registry.insert(ino, handle.clone());
 
         let controller = Controller::create_from_engine(self, ino, handle.clone());
-        if let RegularFile(ref mut file) = handle.write().dispatch() {
+        if let RegularFile(ref file) = handle.dispatch() {
             file.init(controller)
         } else {
             // Can not happen
@@ -107,10 +172,10 @@ impl<'a> Engine<'a> {
         let ino = self.ino_generator.generate();
         let handle = Handle::new_dir(ino, boxed);
 
-        self.registry.write().insert(ino,handle.clone());
+        self.registry.insert(ino, handle.clone());
 
         let controller = Controller::create_from_engine(self, ino, handle.clone());
-        if let Dir(ref mut dir) = handle.write().dispatch() {
+        if let Dir(ref dir) = handle.dispatch() {
             dir.init(controller)
         } else {
             // Can not happen
@@ -119,64 +184,83 @@ impl<'a> Engine<'a> {
         handle
     }
 
-    pub(crate) fn get_registry(&self) -> Registry {
-        self.registry.clone()
-    }
-
-    pub(crate) fn get_ino_generator(&self) -> Arc<InoGenerator> {
-        self.ino_generator.clone()
-    }
+    pub fn add_stream_file<T: 'static>(&mut self, object: T) -> Handle
+    where T: StreamFile + Send + Sync {
 
+        let boxed = Box::new(object);
+        let ino = self.ino_generator.generate();
+        let handle = Handle::new_stream(ino, boxed);
 
+        self.registry.insert(ino, handle.clone());
 
+        let controller = Controller::create_from_engine(self, ino, handle.clone());
+        if let Streamed(ref file) = handle.dispatch() {
+            file.init(controller)
+        } else {
+            // Can not happen
+            panic!();
+        }
+        handle
+    }
 
-    fn start_tokio_runtime(registry: Registry, channel: UnboundedReceiver<ChannelEvent>) {
+    pub fn add_symlink<T: 'static>(&mut self, object: T) -> Handle
+    where T: Symlink + Send + Sync {
 
-        let runtime = channel.for_each(move |event| {
-            println!("Inside the event channel {:?}", event);
+        let boxed = Box::new(object);
+        let ino = self.ino_generator.generate();
+        let handle = Handle::new_symlink(ino, boxed);
 
-            match event {
+        self.registry.insert(ino, handle.clone());
 
-                ChannelEvent::Read{req, ino, fh: _fh, offset, size, reply} => {
-                    let handle = get_handle!(registry, ino, reply);
-                    let file_op = match handle.write().dispatch() {
-                        RegularFile(ref mut file) => {
-                            file.read(req)
-                        }
-                        _ => {
-                            reply.error(EISDIR);
-                            return future::ok(());
-                        }
-                    };
+        let controller = Controller::create_from_engine(self, ino, handle.clone());
+        if let SymbolicLink(ref link) = handle.dispatch() {
+            link.init(controller)
+        } else {
+            // Can not happen
+            panic!();
+        }
+        handle
+    }
 
-                    let finish: Box<dyn Future<Item=(), Error=()> + Send>
-                    = Box::new(file_op.then(move |result|{
+    pub fn add_special<T: 'static>(&mut self, object: T) -> Handle
+    where T: SpecialFile + Send + Sync {
 
-                        match result {
-                            Ok(vec) => {
-                                println!("Request params: Offset {} Size {}", offset, size);
-                                reply.data(&vec[offset as usize..]);
-                            }
-                            Err(error) => {
-                                reply.error(error.get_libc_code());
-                            }
-                        }
+        let boxed = Box::new(object);
+        let ino = self.ino_generator.generate();
+        let handle = Handle::new_special(ino, boxed);
 
-                        future::ok(())
-                    }));
+        self.registry.insert(ino, handle.clone());
 
-                    tokio::executor::spawn(finish);
-                },
+        let controller = Controller::create_from_engine(self, ino, handle.clone());
+        if let Special(ref node) = handle.dispatch() {
+            node.init(controller)
+        } else {
+            // Can not happen
+            panic!();
+        }
+        handle
+    }
 
+    /// Returns the node at the absolute `path`. Entries, that were not found yet, are looked
+    /// up in their directories.
+    pub fn resolve(&self, path: &Path) -> Box<dyn Future<Item=Handle, Error=NodeError> + Send> {
+        Topology::resolve(self.topology.clone(), path, self.registry.clone())
+    }
 
-            }
-            future::ok(())
-        });
+    pub(crate) fn get_registry(&self) -> Registry {
+        self.registry.clone()
+    }
 
+    pub(crate) fn get_ino_generator(&self) -> Arc<InoGenerator> {
+        self.ino_generator.clone()
+    }
 
-        thread::spawn(move || {
-            tokio::run(runtime);
-        });
+    pub(crate) fn get_fh_table(&self) -> Arc<FhTable> {
+        self.fh_table.clone()
+    }
 
+    pub(crate) fn get_topology(&self) -> Arc<Topology> {
+        self.topology.clone()
     }
+
 }
@@ -23,8 +23,13 @@ pub enum NodeError {
     NoSuchEntry,
     IOError,
     PermissionDenied,
+    NotPermitted,
+    NotSupported,
+    NoSuchAttribute,
+    AttributeExists,
     TryAgain,
     ReadOnly,
+    InvalidArgument,
 }
 
 
@@ -41,8 +46,13 @@ impl Error for NodeError {
             NoSuchEntry => "The requested node does not exist",
             IOError => "An I/O Error occurred",
             PermissionDenied => "The user does not have permission to execute the operation",
+            NotPermitted => "The operation is restricted to the owner of the node",
+            NotSupported => "The operation is not supported by this node",
+            NoSuchAttribute => "The extended attribute does not exist",
+            AttributeExists => "The extended attribute already exists",
             TryAgain => "Try again later",
             ReadOnly => "This is a ReadOnly file system",
+            InvalidArgument => "An argument of the operation is invalid",
         }
     }
 }
@@ -59,8 +69,13 @@ impl NodeError {
             NoSuchEntry => ENOENT,
             IOError => EIO,
             PermissionDenied => EACCES,
+            NotPermitted => EPERM,
+            NotSupported => ENOTSUP,
+            NoSuchAttribute => ENODATA,
+            AttributeExists => EEXIST,
             TryAgain => EAGAIN,
             ReadOnly => EROFS,
+            InvalidArgument => EINVAL,
         }
     }
 }
@@ -127,6 +142,7 @@ pub enum DirError {
     NoSuchDirectory,
     IsNotDirectory,
     DirectoryNotEmpty,
+    DirectoryExists,
 }
 
 
@@ -143,6 +159,7 @@ impl Error for DirError {
             NoSuchDirectory => "The directory does not exist",
             IsNotDirectory => "The requested object is not a directory",
             DirectoryNotEmpty => "The directory is not empty",
+            DirectoryExists => "The directory already exists",
         }
     }
 }
@@ -164,12 +181,20 @@ impl DirError {
     }
 
 
+    pub(crate) fn is_not_implemented(&self) -> bool {
+        match *self {
+            DirNodeErr(NotImplemented) => true,
+            _ => false,
+        }
+    }
+
     pub(crate) fn get_libc_code(&self) -> i32 {
         match *self {
             DirNodeErr(ref inner) => inner.get_libc_code(),
             NoSuchDirectory => ENOENT,
             IsNotDirectory => ENOTDIR,
             DirectoryNotEmpty => ENOTEMPTY,
+            DirectoryExists => EEXIST,
         }
     }
 }
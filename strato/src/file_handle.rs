@@ -0,0 +1,172 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::RwLock;
+
+/// Tells the kernel to bypass the page cache for an opened file
+pub(crate) const FOPEN_DIRECT_IO: u32 = 1 << 0;
+/// Tells the kernel to keep the cached content of a file, when it is opened
+const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+/// Tells the kernel to cache the entries of an opened directory
+const FOPEN_CACHE_DIR: u32 = 1 << 3;
+
+/// The state of an opened file or directory.
+/// It is created by `File::open` or `Directory::opendir` and handed to every following
+/// operation on the opened object, until it is released.
+#[derive(Clone)]
+pub struct FileHandle {
+    fh: u64,
+    open_flags: u32,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl FileHandle {
+
+    /// Creates a handle without any state
+    pub fn empty() -> Self {
+        FileHandle {
+            fh: 0,
+            open_flags: 0,
+            state: None,
+        }
+    }
+
+    /// Creates a handle, that carries a user defined state
+    pub fn new<T: Any + Send + Sync>(state: T) -> Self {
+        FileHandle {
+            fh: 0,
+            open_flags: 0,
+            state: Some(Arc::new(state)),
+        }
+    }
+
+    /// Lets the kernel keep the content it cached from earlier opens of the file.
+    /// Use this for files, that do not change behind the kernel's back.
+    pub fn keep_cache(mut self) -> Self {
+        self.open_flags |= FOPEN_KEEP_CACHE;
+        self
+    }
+
+    /// Makes the kernel bypass the page cache, so that every read and write reaches the file.
+    /// Use this for files, whose content changes on its own or whose size is unknown.
+    pub fn direct_io(mut self) -> Self {
+        self.open_flags |= FOPEN_DIRECT_IO;
+        self
+    }
+
+    /// Lets the kernel cache the entries of an opened directory.
+    /// Only has an effect on kernels, that support directory caching.
+    pub fn cache_dir(mut self) -> Self {
+        self.open_flags |= FOPEN_CACHE_DIR;
+        self
+    }
+
+    pub(crate) fn get_open_flags(&self) -> u32 {
+        self.open_flags
+    }
+
+    /// Returns the number, by which the kernel references this open object
+    pub fn get_fh(&self) -> u64 {
+        self.fh
+    }
+
+    /// Returns the state of this handle, if it is of type `T`
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.state.as_ref().and_then(|state| state.downcast_ref::<T>())
+    }
+
+}
+
+impl fmt::Debug for FileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fh:{}", self.fh)
+    }
+}
+
+
+/// The thread safe table of all currently opened files and directories.
+/// Every handle is stored together with the ino of the node it belongs to.
+#[derive(Debug)]
+pub(crate) struct FhTable {
+    next_fh : AtomicU64,
+    handles : RwLock<Handles>,
+}
+
+#[derive(Debug, Default)]
+struct Handles {
+    by_fh : HashMap<u64, (u64, FileHandle)>,
+    // The number of handles of each opened node
+    open : HashMap<u64, usize>,
+}
+
+impl FhTable {
+
+    pub(crate) fn new() -> Self {
+        FhTable {
+            next_fh : AtomicU64::new(1),
+            handles : RwLock::new(Handles::default()),
+        }
+    }
+
+    /// Assigns a new fh to the handle and stores it until it is removed
+    pub(crate) fn insert(&self, ino: u64, mut handle: FileHandle) -> FileHandle {
+        handle.fh = self.next_fh.fetch_add(1, Ordering::SeqCst);
+        let mut handles = self.handles.write();
+        handles.by_fh.insert(handle.fh, (ino, handle.clone()));
+        *handles.open.entry(ino).or_default() += 1;
+        handle
+    }
+
+    pub(crate) fn get(&self, fh: u64) -> Option<FileHandle> {
+        self.handles.read().by_fh.get(&fh).map(|(_, handle)| handle.clone())
+    }
+
+    pub(crate) fn remove(&self, fh: u64) -> Option<FileHandle> {
+        let mut handles = self.handles.write();
+        let (ino, handle) = handles.by_fh.remove(&fh)?;
+        if let Some(count) = handles.open.get_mut(&ino) {
+            *count -= 1;
+            if *count == 0 {
+                handles.open.remove(&ino);
+            }
+        }
+        Some(handle)
+    }
+
+    /// Returns true, if the node is opened at least once
+    pub(crate) fn is_open(&self, ino: u64) -> bool {
+        self.handles.read().open.contains_key(&ino)
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table() {
+        let table = FhTable::new();
+
+        let first = table.insert(2, FileHandle::new(42u32));
+        let second = table.insert(3, FileHandle::empty());
+        assert_ne!(first.get_fh(), second.get_fh());
+
+        let fetched = table.get(first.get_fh()).unwrap();
+        assert_eq!(fetched.get::<u32>(), Some(&42));
+        assert_eq!(fetched.get::<u64>(), None);
+
+        let again = table.insert(2, FileHandle::empty());
+        assert!(table.remove(again.get_fh()).is_some());
+        assert!(table.is_open(2));
+        assert!(table.remove(first.get_fh()).is_some());
+        assert!(table.get(first.get_fh()).is_none());
+        assert!(!table.is_open(2));
+        assert!(table.get(second.get_fh()).is_some());
+    }
+
+}
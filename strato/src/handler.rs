@@ -4,39 +4,64 @@ use std::fmt;
 
 use std::ops::Deref;
 
-use parking_lot::RwLock;
-
-use crate::{FileImpl, DirImpl};
+use crate::{FileImpl, DirImpl, StreamImpl, SymlinkImpl, SpecialImpl};
 use self::HandleDispatcher::*;
 
 #[derive (Clone, Debug)]
-pub struct Handle (Arc<RwLock<HandleInner>>);
+pub struct Handle (Arc<HandleInner>);
 
 impl Handle {
 
     pub(crate) fn new_file(ino: u64, object: FileImpl) -> Self {
-        Handle(Arc::new(RwLock::new(
+        Handle(Arc::new(
             HandleInner {
                 ino,
                 dispatch : RegularFile(object),
             }
-        )))
+        ))
     }
 
     pub(crate) fn new_dir(ino: u64, object: DirImpl) -> Self {
-        Handle(Arc::new(RwLock::new(
+        Handle(Arc::new(
             HandleInner {
                 ino,
                 dispatch : Dir(object),
             }
-        )))
+        ))
+    }
+
+    pub(crate) fn new_stream(ino: u64, object: StreamImpl) -> Self {
+        Handle(Arc::new(
+            HandleInner {
+                ino,
+                dispatch : Streamed(object),
+            }
+        ))
+    }
+
+    pub(crate) fn new_symlink(ino: u64, object: SymlinkImpl) -> Self {
+        Handle(Arc::new(
+            HandleInner {
+                ino,
+                dispatch : SymbolicLink(object),
+            }
+        ))
+    }
+
+    pub(crate) fn new_special(ino: u64, object: SpecialImpl) -> Self {
+        Handle(Arc::new(
+            HandleInner {
+                ino,
+                dispatch : Special(object),
+            }
+        ))
     }
 
 }
 
 impl Deref for Handle {
-    type Target = Arc<RwLock<HandleInner>>;
-    fn deref(&self) -> &Arc<RwLock<HandleInner>> {
+    type Target = HandleInner;
+    fn deref(&self) -> &HandleInner {
         &self.0
     }
 }
@@ -44,7 +69,10 @@ impl Deref for Handle {
 
 pub(crate) enum HandleDispatcher {
     RegularFile(FileImpl),
-    Dir(DirImpl)
+    Dir(DirImpl),
+    Streamed(StreamImpl),
+    SymbolicLink(SymlinkImpl),
+    Special(SpecialImpl),
 }
 
 
@@ -63,18 +91,33 @@ impl Eq for HandleInner {}
 
 impl HandleInner {
 
-    pub(crate) fn dispatch_ref(&self) -> &HandleDispatcher {
+    /// Returns the node behind the handle. Requests to the same node are dispatched
+    /// concurrently, so nodes manage their mutable state themselves.
+    pub(crate) fn dispatch(&self) -> &HandleDispatcher {
         &self.dispatch
     }
 
-    pub(crate) fn dispatch(&mut self) -> &mut HandleDispatcher {
-        &mut self.dispatch
-    }
-
     pub(crate) fn get_ino(&self) -> u64 {
         self.ino
     }
 
+    pub(crate) fn is_dir(&self) -> bool {
+        match self.dispatch {
+            Dir(_) => true,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn destroy(&self) {
+        match self.dispatch {
+            RegularFile(ref file) => file.destroy(),
+            Dir(ref dir) => dir.destroy(),
+            Streamed(ref file) => file.destroy(),
+            SymbolicLink(ref link) => link.destroy(),
+            Special(ref node) => node.destroy(),
+        }
+    }
+
 }
 
 impl fmt::Debug for HandleInner {
@@ -87,6 +130,15 @@ impl fmt::Debug for HandleInner {
             RegularFile(_) => {
                 write!(f, "ino:{} (File)", self.ino)
             }
+            Streamed(_) => {
+                write!(f, "ino:{} (Stream)", self.ino)
+            }
+            SymbolicLink(_) => {
+                write!(f, "ino:{} (Symlink)", self.ino)
+            }
+            Special(ref node) => {
+                write!(f, "ino:{} ({:?})", self.ino, node.kind())
+            }
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::RwLock;
+
+use crate::handler::Handle;
+
+/// The number of shards of the inode table. Inos are handed out sequentially,
+/// so taking their low bits spreads the nodes evenly.
+const SHARDS: usize = 64;
+
+/// A shard is aligned to a cache line, so that threads working on neighbouring
+/// shards do not invalidate each others lock.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Shard(RwLock<HashMap<u64, Handle>>);
+
+/// The thread safe table of all nodes, indexed by their ino.
+/// It is split into shards with their own lock. Lookups only share the lock of a single shard
+/// with other lookups, and an insert only blocks the readers of the shard it goes into.
+#[derive(Debug)]
+pub(crate) struct InodeTable {
+    shards : Vec<Shard>,
+    len : AtomicUsize,
+}
+
+impl InodeTable {
+
+    pub(crate) fn new() -> Self {
+        InodeTable {
+            shards : (0..SHARDS).map(|_| Shard::default()).collect(),
+            len : AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, ino: u64) -> &RwLock<HashMap<u64, Handle>> {
+        &self.shards[ino as usize % SHARDS].0
+    }
+
+    pub(crate) fn get(&self, ino: u64) -> Option<Handle> {
+        self.shard(ino).read().get(&ino).cloned()
+    }
+
+    pub(crate) fn insert(&self, ino: u64, handle: Handle) {
+        if self.shard(ino).write().insert(ino, handle).is_none() {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn remove(&self, ino: u64) -> Option<Handle> {
+        let removed = self.shard(ino).write().remove(&ino);
+        if removed.is_some() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Returns the number of nodes in the file system
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::hint::black_box;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::{Node, File};
+
+    struct EmptyFile;
+
+    impl Node for EmptyFile {}
+    impl File for EmptyFile {}
+
+    fn filled(nodes: u64) -> InodeTable {
+        let table = InodeTable::new();
+        for ino in 1..=nodes {
+            table.insert(ino, Handle::new_file(ino, Box::new(EmptyFile)));
+        }
+        table
+    }
+
+    #[test]
+    fn table() {
+        let table = filled(100);
+        assert_eq!(table.len(), 100);
+
+        assert_eq!(table.get(42).map(|handle| handle.get_ino()), Some(42));
+        assert!(table.get(101).is_none());
+
+        // Replacing a node does not change the count
+        table.insert(42, Handle::new_file(42, Box::new(EmptyFile)));
+        assert_eq!(table.len(), 100);
+
+        assert!(table.remove(42).is_some());
+        assert!(table.remove(42).is_none());
+        assert!(table.get(42).is_none());
+        assert_eq!(table.len(), 99);
+    }
+
+    const NODES: u64 = 100_000;
+    const LOOKUPS: usize = 1 << 20;
+    const ROUNDS: u32 = 20;
+
+    /// Returns the average time of a round of the same number of lookups, split across
+    /// `threads`. The threads are started once and wait for each other around every round,
+    /// so only the lookups are timed.
+    fn measure<F>(threads: usize, lookup: F) -> Duration
+    where F: Fn(u64) + Send + Sync + 'static {
+        let lookup = Arc::new(lookup);
+        let barrier = Arc::new(Barrier::new(threads + 1));
+        let workers: Vec<_> = (0..threads).map(|worker| {
+            let (lookup, barrier) = (lookup.clone(), barrier.clone());
+            thread::spawn(move || {
+                for _ in 0..ROUNDS {
+                    barrier.wait();
+                    let mut ino = worker as u64;
+                    for _ in 0..LOOKUPS / threads {
+                        ino = (ino + 7919) % NODES + 1;
+                        lookup(ino);
+                    }
+                    barrier.wait();
+                }
+            })
+        }).collect();
+
+        let mut total = Duration::default();
+        for _ in 0..ROUNDS {
+            barrier.wait();
+            let start = Instant::now();
+            barrier.wait();
+            total += start.elapsed();
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        total / ROUNDS
+    }
+
+    /// Compares concurrent lookups with the `RwLock<BTreeMap>`, that the table replaced.
+    /// With the lookups not contending, the time per round drops with every added core.
+    /// Run with `cargo test --release -- --ignored --nocapture lookups`.
+    #[test]
+    #[ignore]
+    fn lookups() {
+        let table = Arc::new(filled(NODES));
+        let baseline: BTreeMap<u64, Handle> = (1..=NODES)
+            .map(|ino| (ino, Handle::new_file(ino, Box::new(EmptyFile))))
+            .collect();
+        let baseline = Arc::new(RwLock::new(baseline));
+
+        for &threads in &[1, 2, 4, 8] {
+            let sharded = {
+                let table = table.clone();
+                measure(threads, move |ino| { black_box(table.get(ino)); })
+            };
+            let locked = {
+                let baseline = baseline.clone();
+                measure(threads, move |ino| { black_box(baseline.read().get(&ino).cloned()); })
+            };
+            println!("{} threads: {:?} per round, {:?} with a RwLock<BTreeMap>",
+                     threads, sharded, locked);
+        }
+    }
+
+}
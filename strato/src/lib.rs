@@ -1,10 +1,15 @@
 #![feature(integer_atomics)]
 extern crate parking_lot;
-extern crate fuse;
+extern crate fuse_strato;
 extern crate libc;
+extern crate bytes;
+#[macro_use]
+extern crate log;
 
 mod driver;
+mod dispatch;
 mod utils;
+mod inode_table;
 
 
 mod engine;
@@ -16,62 +21,352 @@ pub use crate::handler::Handle;
 mod controller;
 pub use crate::controller::Controller;
 
+mod stream;
+pub use crate::stream::{FileStream, SeekPolicy};
+
+mod file_handle;
+pub use crate::file_handle::FileHandle;
+
+mod special;
+pub use crate::special::SpecialKind;
+
+mod topology;
+
+mod permission;
+pub use crate::permission::PermissionMode;
+
+mod xattr;
+pub use crate::xattr::XattrMode;
+
+mod statfs;
+pub use crate::statfs::FsStats;
+
+mod lock;
+pub use crate::lock::{FileLock, LockKind};
+
+mod dir_reply;
+pub use crate::dir_reply::DirectoryReply;
+
+mod read_data;
+pub use crate::read_data::ReadData;
+
 pub mod link;
 pub mod error;
 
 use std::sync::Arc;
-use std::collections::BTreeMap;
-
-use parking_lot::RwLock;
+use std::path::PathBuf;
 
-use crate::link::NodeEntry;
+use crate::inode_table::InodeTable;
+use crate::link::{NodeEntry, AttrChanges};
 pub use crate::controller::Request;
+pub use bytes::Bytes;
 use crate::error::{NodeError, FileError, DirError};
 
 
 use futures::future;
 use futures::future::Future;
 
-pub(crate) type Registry = Arc<RwLock<BTreeMap<u64, Handle>>>;
+pub(crate) type Registry = Arc<InodeTable>;
 
 pub(crate) type FileImpl = Box<dyn File + Send + Sync>;
 pub(crate) type DirImpl = Box<dyn Directory + Send + Sync>;
+pub(crate) type StreamImpl = Box<dyn StreamFile + Send + Sync>;
+pub(crate) type SymlinkImpl = Box<dyn Symlink + Send + Sync>;
+pub(crate) type SpecialImpl = Box<dyn SpecialFile + Send + Sync>;
 
 
-// TODO: F U T U R E S
 // TODO: Add proper logging support
 
 /// This trait contains all the base functions, that need to be implemented for the object
 /// to behave as a node in the file system.
+///
+/// Operations return futures, which are driven by the runtime of the engine. Requests to the
+/// same node are dispatched concurrently, so nodes with mutable state need their own interior
+/// mutability, e.g. a `RwLock` around the parts that change.
 pub trait Node {
 
-    fn init(&mut self, _: Controller) {}
+    fn init(&self, _: Controller) {}
+
+    /// Called, when the node has lost its last directory entry and is not opened anymore.
+    /// The node is removed from the file system afterwards.
+    fn destroy(&self) {}
 
-    fn read_attributes(&mut self, _: Request, _: NodeEntry) -> Result<NodeEntry, NodeError> {
-        Err(NodeError::new(NodeError::NotImplemented))
+    fn read_attributes(&self, _: Request, _: NodeEntry)
+        -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotImplemented)))
+    }
+
+    /// Changes the attributes of the node, as requested by `chmod`, `chown`, `truncate` or
+    /// `utimens`. `attr` holds the current attributes, as returned by `read_attributes`.
+    /// `NodeEntry::apply` can be used to copy the changes onto it.
+    /// Returns the attributes after the change.
+    fn set_attributes(&self, _: Request, _changes: AttrChanges, _attr: NodeEntry)
+        -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotImplemented)))
+    }
+
+    /// Returns the value of the extended attribute `name`.
+    /// Fails with `NodeError::NoSuchAttribute`, if the attribute does not exist.
+    fn get_xattr(&self, _: Request, _name: String)
+        -> Box<dyn Future<Item=Vec<u8>, Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotSupported)))
+    }
+
+    /// Sets the extended attribute `name` to `value`. `mode` tells, whether the attribute
+    /// must or must not exist already.
+    fn set_xattr(&self, _: Request, _name: String, _value: Vec<u8>, _mode: XattrMode)
+        -> Box<dyn Future<Item=(), Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotSupported)))
+    }
+
+    /// Returns the names of all extended attributes of this node.
+    fn list_xattr(&self, _: Request)
+        -> Box<dyn Future<Item=Vec<String>, Error=NodeError> + Send> {
+        Box::new(future::ok(Vec::new()))
+    }
+
+    /// Removes the extended attribute `name`.
+    /// Fails with `NodeError::NoSuchAttribute`, if the attribute does not exist.
+    fn remove_xattr(&self, _: Request, _name: String)
+        -> Box<dyn Future<Item=(), Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotSupported)))
     }
 
 }
 
 
+/// A node, that contains entries.
+///
+/// Strato counts the entries of the nodes created through `create`, `mkdir`, `mknod` and
+/// `symlink`, and tears these nodes down, once their last entry is removed. Any other node may
+/// be served by directories, that strato does not know of, so it is never torn down by
+/// unlinking, and reports the link count set with `NodeEntry::nlink`.
 pub trait Directory: Node {
 
-    fn lookup(&mut self, _: Request, _: String) -> Result<NodeEntry, NodeError> {
-        Err(NodeError::new(NodeError::NotImplemented))
+    /// Returns true, if the directory lists and looks up "." and ".." itself.
+    /// Otherwise, strato adds them, so that `readdir` and `lookup` only see the real entries.
+    fn lists_dots(&self) -> bool {
+        false
+    }
+
+    fn lookup(&self, _: Request, _: String)
+        -> Box<dyn Future<Item=NodeEntry, Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotImplemented)))
+    }
+
+    /// Opens the directory for reading. The returned handle is passed to every `readdir`
+    /// until the directory is released. See `FileHandle::cache_dir` to cache the entries.
+    fn opendir(&self, _: Request, _flags: u32)
+        -> Box<dyn Future<Item=FileHandle, Error=DirError> + Send> {
+        Box::new(future::ok(FileHandle::empty()))
+    }
+
+    /// Lists the entries following the position `cookie` into `reply`, until it is full,
+    /// and returns the reply. A `cookie` of 0 starts the listing from the beginning.
+    fn readdir(&self, _: Request, _fh: FileHandle, _cookie: u64, _reply: DirectoryReply)
+        -> Box<dyn Future<Item=DirectoryReply, Error=DirError> + Send> {
+        Box::new(future::err(DirError::new(NodeError::NotImplemented)))
+    }
+
+    fn releasedir(&self, _: Request, _fh: FileHandle)
+        -> Box<dyn Future<Item=(), Error=DirError> + Send> {
+        Box::new(future::ok(()))
+    }
+
+    /// Reports the usage of the file system, e.g. for `df`. This is asked of the root
+    /// directory, unless the statistics of another directory are queried.
+    /// `stats` is prefilled with the number of nodes in use and no blocks.
+    fn statfs(&self, _: Request, stats: FsStats)
+        -> Box<dyn Future<Item=FsStats, Error=NodeError> + Send> {
+        Box::new(future::ok(stats))
+    }
+
+    /// Creates a new regular file in this directory. The node backing the returned entry
+    /// is usually spawned via `Controller::add_file`. It is opened right away afterwards.
+    fn create(&self, _: Request, _name: String, _mode: u32, _flags: u32)
+        -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Creates a new node, that is not a directory, in this directory.
+    /// For named pipes, sockets and devices, `SpecialKind::from_mode` decodes the kind and the
+    /// node is usually spawned via `Controller::add_special`.
+    fn mknod(&self, _: Request, _name: String, _mode: u32, _rdev: u32)
+        -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Creates a new subdirectory, usually spawned via `Controller::add_directory`.
+    fn mkdir(&self, _: Request, _name: String, _mode: u32)
+        -> Box<dyn Future<Item=NodeEntry, Error=DirError> + Send> {
+        Box::new(future::err(DirError::new(NodeError::NotImplemented)))
+    }
+
+    /// Removes the entry of a node, that is not a directory.
+    fn unlink(&self, _: Request, _name: String)
+        -> Box<dyn Future<Item=(), Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Removes the entry of an empty subdirectory.
+    fn rmdir(&self, _: Request, _name: String)
+        -> Box<dyn Future<Item=(), Error=DirError> + Send> {
+        Box::new(future::err(DirError::new(NodeError::NotImplemented)))
+    }
+
+    /// Renames an entry within this directory, replacing an existing entry `new_name`.
+    fn rename(&self, _: Request, _name: String, _new_name: String)
+        -> Box<dyn Future<Item=(), Error=DirError> + Send> {
+        Box::new(future::err(DirError::new(NodeError::NotImplemented)))
     }
 
-    fn readdir(&mut self, _: Request) -> Result<Vec<NodeEntry>, DirError> {
-        Err(DirError::new(NodeError::NotImplemented))
+    /// Removes an entry, that is moved into another directory, and returns it.
+    /// If this is not implemented, moves out of this directory fail with `EXDEV`.
+    ///
+    /// A move between directories is not atomic: the entry is missing from both directories
+    /// between `move_out` and `move_in`. If the destination refuses the entry, it is handed
+    /// back with `move_in`. Should that fail as well, the entry is lost and the rename fails
+    /// with `EIO`, while its node stays registered until it is not used anymore.
+    fn move_out(&self, _: Request, _name: String)
+        -> Box<dyn Future<Item=NodeEntry, Error=DirError> + Send> {
+        Box::new(future::err(DirError::new(NodeError::NotImplemented)))
+    }
+
+    /// Inserts an entry, that was moved from another directory, under `name`, replacing an
+    /// existing entry. On failure, the entry is handed back to its old directory through
+    /// `move_in`, which is not atomic either.
+    fn move_in(&self, _: Request, _name: String, _entry: NodeEntry)
+        -> Box<dyn Future<Item=(), Error=DirError> + Send> {
+        Box::new(future::err(DirError::new(NodeError::NotImplemented)))
+    }
+
+    /// Creates a new symbolic link, usually spawned via `Controller::add_symlink`.
+    fn symlink(&self, _: Request, _name: String, _target: PathBuf)
+        -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Adds another entry for an existing node to this directory.
+    fn link(&self, _: Request, _target: Handle, _name: String)
+        -> Box<dyn Future<Item=NodeEntry, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
     }
 
 }
 
 pub trait File: Node {
 
-    fn read(&mut self, _: Request) -> Box<dyn Future<Item=Vec<u8>, Error=FileError> + Send> {
+    /// Opens the file. The returned handle is passed to every following operation on this
+    /// open file and dropped after `release`. By default, the kernel drops its cache of the
+    /// file on every open, see `FileHandle::keep_cache` and `FileHandle::direct_io`.
+    fn open(&self, _: Request, _flags: u32)
+        -> Box<dyn Future<Item=FileHandle, Error=FileError> + Send> {
+        Box::new(future::ok(FileHandle::empty()))
+    }
+
+    /// Reads up to `size` bytes from the file, starting at `offset`.
+    /// Returning fewer bytes than requested signals a short read, an empty vector signals
+    /// the end of the file. Data exceeding `size` is cut off by the framework.
+    /// Files backed by a file on the host can return `ReadData::Splice` instead, so that the
+    /// data does not pass through userspace.
+    fn read(&self, _: Request, _fh: FileHandle, _offset: i64, _size: u32)
+        -> Box<dyn Future<Item=ReadData, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Writes `data` to the file at `offset` and returns the number of bytes written.
+    /// Data of 64KB and more shares the buffer, the request was read into. Holding on to it
+    /// keeps the whole buffer of `Engine::max_write` bytes from being reused, so copy it if it
+    /// is kept beyond the write.
+    fn write(&self, _: Request, _fh: FileHandle, _offset: i64, _data: Bytes, _flags: u32)
+        -> Box<dyn Future<Item=u32, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Called on every `close` of a file descriptor. Since one open file can be shared
+    /// by several descriptors, this can be called multiple times per open.
+    fn flush(&self, _: Request, _fh: FileHandle, _lock_owner: u64)
+        -> Box<dyn Future<Item=(), Error=FileError> + Send> {
+        Box::new(future::ok(()))
+    }
+
+    /// Persists the content of the file. If `datasync` is set, only the data but not the
+    /// metadata needs to be persisted.
+    fn fsync(&self, _: Request, _fh: FileHandle, _datasync: bool)
+        -> Box<dyn Future<Item=(), Error=FileError> + Send> {
+        Box::new(future::ok(()))
+    }
+
+    /// Called, when the last reference to an open file is closed.
+    fn release(&self, _: Request, _fh: FileHandle, _flags: u32)
+        -> Box<dyn Future<Item=(), Error=FileError> + Send> {
+        Box::new(future::ok(()))
+    }
+
+    /// Returns true, if the file implements `get_lock` and `set_lock` itself, e.g. to lock
+    /// across several machines. Otherwise, the record locks are managed by strato.
+    /// `flock(2)` locks are always managed by strato.
+    fn manages_locks(&self) -> bool {
+        false
+    }
+
+    /// Returns a lock, that conflicts with `lock`, if there is any.
+    /// Only called, if the file manages its locks.
+    fn get_lock(&self, _: Request, _fh: FileHandle, _lock: FileLock)
+        -> Box<dyn Future<Item=Option<FileLock>, Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+    /// Acquires, changes or releases a lock. Only called, if the file manages its locks.
+    /// If the lock conflicts, this fails with `NodeError::TryAgain`, unless `sleep` is set.
+    /// Then it resolves, once the lock was acquired. The locks of an owner are released on
+    /// `flush`.
+    fn set_lock(&self, _: Request, _fh: FileHandle, _lock: FileLock, _sleep: bool)
+        -> Box<dyn Future<Item=(), Error=FileError> + Send> {
+        Box::new(future::err(FileError::new(NodeError::NotImplemented)))
+    }
+
+}
+
+/// A file, whose content is produced as a stream, e.g. the output of a decompressor or a command.
+/// Every open of the file gets its own stream, which is buffered by the framework and used to
+/// serve sequential reads. Since the size of the content is not known in advance, the kernel
+/// page cache is bypassed for these files.
+pub trait StreamFile: Node {
+
+    fn open_stream(&self, _: Request)
+        -> Box<dyn Future<Item=FileStream, Error=FileError> + Send> {
         Box::new(future::err(FileError::new(NodeError::NotImplemented)))
     }
 
+    /// Defines how reads before the current position of the stream are handled.
+    fn seek_policy(&self) -> SeekPolicy {
+        SeekPolicy::Reopen
+    }
+
+}
+
+/// A symbolic link, that points to another path.
+pub trait Symlink: Node {
+
+    /// Returns the path, this link points to.
+    fn readlink(&self, _: Request) -> Box<dyn Future<Item=PathBuf, Error=NodeError> + Send> {
+        Box::new(future::err(NodeError::new(NodeError::NotImplemented)))
+    }
+
+}
+
+/// A named pipe, socket or device node. The file system only reports these nodes,
+/// opening and accessing them is handled by the kernel.
+pub trait SpecialFile: Node {
+
+    fn kind(&self) -> SpecialKind;
+
+    /// The device number of a character or block device
+    fn rdev(&self) -> u32 {
+        0
+    }
+
 }
 
 
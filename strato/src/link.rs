@@ -1,8 +1,13 @@
+use std::time::Duration;
+
 use time::Timespec;
 
-use fuse::{FileType, FileAttr};
+use fuse_strato::{FileType, FileAttr};
 
 use crate::handler::{Handle, HandleDispatcher::*};
+use crate::topology::Topology;
+use crate::file_handle::FileHandle;
+use crate::utils::to_system_time;
 
 macro_rules! getter {
     ($a: ident, $b:ident, $c:ty, $doc:tt) => {
@@ -30,19 +35,30 @@ pub struct NodeEntry {
     handle : Handle,
 
     size: u64,
+    blocks: Option<u64>,
+    blksize: u32,
+    nlink: Option<u32>,
+
+    perm: Option<u16>,
+    uid: u32,
+    gid: u32,
+    rdev: Option<u32>,
+    flags: u32,
 
     atime: Timespec,
     mtime: Timespec,
     ctime: Timespec,
     crtime: Timespec,
 
-    ttl: Timespec,
+    entry_ttl: Duration,
+    attr_ttl: Duration,
 }
 
 
 impl NodeEntry {
 
-    /// Creates a directory entry
+    /// Creates a directory entry.
+    /// The entry is owned by the user and group, that mounted the file system.
     pub fn new(name : String, handle: Handle) -> Self {
 
         let epoch = Timespec::new(0, 0);
@@ -52,13 +68,23 @@ impl NodeEntry {
             handle : handle.clone(),
 
             size: 0,
+            blocks: None,
+            blksize: 4096,
+            nlink: None,
+
+            perm: None,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+            rdev: None,
+            flags: 0,
 
             atime: epoch,
             mtime: epoch,
             ctime: epoch,
             crtime: epoch,
 
-            ttl: epoch,
+            entry_ttl: Duration::from_secs(0),
+            attr_ttl: Duration::from_secs(0),
         }
     }
 
@@ -66,10 +92,76 @@ impl NodeEntry {
         self.name.clone()
     }
 
+    pub fn get_handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
 
     getter!(get_size, size, u64, "Returns the size of the entry");
     setter!(size, u64, "Set the size of the entry");
 
+    /// Returns the number of 512 byte blocks allocated to the entry.
+    /// Unless set explicitly, this is derived from the size.
+    pub fn get_blocks(&self) -> u64 {
+        self.blocks.unwrap_or((self.size + 511) / 512)
+    }
+
+    /// Set the number of 512 byte blocks allocated to the entry, e.g. for sparse files.
+    pub fn blocks(&mut self, val: u64) -> &Self {
+        self.blocks = Some(val);
+        self
+    }
+
+    getter!(get_blksize, blksize, u32, "Returns the preferred block size for I/O");
+    setter!(blksize, u32, "Set the preferred block size for I/O. Defaults to 4096.");
+
+    /// Returns the number of links, the directory reported for the entry, if it did.
+    pub fn get_nlink(&self) -> Option<u32> {
+        self.nlink
+    }
+
+    /// Set the number of directory entries, that refer to the node. Strato only counts the
+    /// entries of nodes created through the file system, so this is reported for all others.
+    /// Directories count their own `.` and the `..` of each subdirectory.
+    pub fn nlink(&mut self, val: u32) -> &Self {
+        self.nlink = Some(val);
+        self
+    }
+
+    /// Returns the permission bits of the entry, if they were set.
+    /// Otherwise, `0o755` is reported for directories, `0o777` for links and `0o644` for
+    /// everything else.
+    pub fn get_perm(&self) -> Option<u16> {
+        self.perm
+    }
+
+    /// Set the permission bits of the entry, e.g. `0o640`.
+    pub fn perm(&mut self, val: u16) -> &Self {
+        self.perm = Some(val & 0o7777);
+        self
+    }
+
+    getter!(get_uid, uid, u32, "Returns the user id of the owner");
+    setter!(uid, u32, "Set the user id of the owner. Defaults to the mounting user.");
+
+    getter!(get_gid, gid, u32, "Returns the group id of the owner");
+    setter!(gid, u32, "Set the group id of the owner. Defaults to the mounting group.");
+
+    /// Returns the device number of the entry, if it was set.
+    /// Otherwise, the number reported by a `SpecialFile` is used.
+    pub fn get_rdev(&self) -> Option<u32> {
+        self.rdev
+    }
+
+    /// Set the device number of the entry.
+    pub fn rdev(&mut self, val: u32) -> &Self {
+        self.rdev = Some(val);
+        self
+    }
+
+    getter!(get_flags, flags, u32, "Returns the flags of the entry (macOS only)");
+    setter!(flags, u32, "Set the flags of the entry, as in `chflags(2)` (macOS only).");
+
     getter!(get_atime, atime, Timespec, "Returns the last accessed time");
     setter!(atime, Timespec, "Set the time the file was last accessed.");
 
@@ -83,51 +175,146 @@ impl NodeEntry {
     setter!(crtime, Timespec, "Set the time the file was created.");
 
 
-    getter!(get_ttl, ttl, Timespec, "Set the time, this `Directory Entry` is considered valid.\
-        This OS will cache the attributes for this time. Afterwards, the OS will query the \
-        `getattr` again.");
-    setter!(ttl, Timespec, "Get the time, this `Directory Entry` is considered valid.");
-    
+    getter!(get_entry_ttl, entry_ttl, Duration, "Returns how long the name of this entry is \
+        considered valid.");
+    setter!(entry_ttl, Duration, "Set how long the OS caches the name of this entry. \
+        Afterwards, the OS will query `lookup` again.");
+
+    getter!(get_attr_ttl, attr_ttl, Duration, "Returns how long the attributes of this entry \
+        are considered valid.");
+    setter!(attr_ttl, Duration, "Set how long the OS caches the attributes of this entry. \
+        Afterwards, the OS will query `read_attributes` again.");
 
-    pub(crate) fn to_attr(&self) -> FileAttr{
+    /// Set how long the OS caches both the name and the attributes of this entry.
+    pub fn ttl(&mut self, val: Duration) -> &Self {
+        self.entry_ttl = val;
+        self.attr_ttl = val;
+        self
+    }
+    
 
-        let reader = self.handle.read();
+    pub(crate) fn to_attr(&self, topology: &Topology) -> FileAttr{
 
-        let file_type = match reader.dispatch_ref() {
+        let file_type = match self.handle.dispatch() {
             Dir(_) => FileType::Directory,
-            RegularFile(_) => FileType::RegularFile,
+            RegularFile(_) | Streamed(_) => FileType::RegularFile,
+            SymbolicLink(_) => FileType::Symlink,
+            Special(ref node) => node.kind().to_file_type(),
+        };
+
+        let rdev = match (self.rdev, self.handle.dispatch()) {
+            (Some(rdev), _) => rdev,
+            (None, Special(ref node)) => node.rdev(),
+            (None, _) => 0,
         };
 
-        // TODO: Let these values either be user settable or find a way to set them programmatically
+        let perm = self.perm.unwrap_or_else(|| match file_type {
+            FileType::Directory => 0o755,
+            FileType::Symlink => 0o777,
+            _ => 0o644,
+        });
+
         FileAttr {
-            ino: reader.get_ino(),
+            ino: self.handle.get_ino(),
             size: self.size,
-            blocks: 1,
-            atime: self.atime,
-            mtime: self.mtime,
-            ctime: self.ctime,
-            crtime: self.crtime,
+            blocks: self.get_blocks(),
+            atime: to_system_time(self.atime),
+            mtime: to_system_time(self.mtime),
+            ctime: to_system_time(self.ctime),
+            crtime: to_system_time(self.crtime),
             kind: file_type,
-            perm: 0o744,
-            nlink: 1,
-            uid: 1000,
-            gid: 1000,
-            rdev: 0,
-            flags: 0,
+            perm,
+            nlink: topology.nlink(self.handle.get_ino(), self.handle.is_dir(), self.nlink),
+            uid: self.uid,
+            gid: self.gid,
+            rdev,
+            flags: self.flags,
+            blksize: self.blksize,
         }
 
     }
 
+    /// Applies the changed mode, ownership, size and times to the entry.
+    pub fn apply(&mut self, changes: &AttrChanges) -> &Self {
+        if let Some(mode) = changes.mode {
+            self.perm(mode as u16);
+        }
+        if let Some(uid) = changes.uid {
+            self.uid = uid;
+        }
+        if let Some(gid) = changes.gid {
+            self.gid = gid;
+        }
+        if let Some(size) = changes.size {
+            self.size = size;
+        }
+        if let Some(atime) = changes.atime {
+            self.atime = atime;
+        }
+        if let Some(mtime) = changes.mtime {
+            self.mtime = mtime;
+        }
+        self
+    }
+
     pub(crate) fn to_reply(&self) -> (u64, FileType, String) {
-        match self.handle.read().dispatch_ref() {
+        match self.handle.dispatch() {
             Dir(_) => {
-                (self.handle.read().get_ino(), FileType::Directory, self.name.clone())
+                (self.handle.get_ino(), FileType::Directory, self.name.clone())
+            }
+            RegularFile(_) | Streamed(_) => {
+                (self.handle.get_ino(), FileType::RegularFile, self.name.clone())
             }
-            RegularFile(_) => {
-                (self.handle.read().get_ino(), FileType::RegularFile, self.name.clone())
+            SymbolicLink(_) => {
+                (self.handle.get_ino(), FileType::Symlink, self.name.clone())
+            }
+            Special(ref node) => {
+                (self.handle.get_ino(), node.kind().to_file_type(), self.name.clone())
             }
         }
 
     }
 
-}
\ No newline at end of file
+}
+
+
+/// The attributes, that are requested to change by `chmod`, `chown`, `truncate` or `utimens`.
+/// Only the attributes, that are set, are meant to be changed.
+#[derive (Clone, Debug, Default)]
+pub struct AttrChanges {
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    size: Option<u64>,
+    atime: Option<Timespec>,
+    mtime: Option<Timespec>,
+    fh: Option<FileHandle>,
+}
+
+impl AttrChanges {
+
+    pub(crate) fn new(mode: Option<u32>, uid: Option<u32>, gid: Option<u32>, size: Option<u64>,
+                      atime: Option<Timespec>, mtime: Option<Timespec>, fh: Option<FileHandle>)
+        -> Self {
+        AttrChanges { mode, uid, gid, size, atime, mtime, fh }
+    }
+
+    getter!(mode, mode, Option<u32>, "Returns the new permission bits");
+    getter!(uid, uid, Option<u32>, "Returns the new user id of the owner");
+    getter!(gid, gid, Option<u32>, "Returns the new group id of the owner");
+    getter!(size, size, Option<u64>, "Returns the new size, the file is truncated or extended to");
+    getter!(atime, atime, Option<Timespec>, "Returns the new last accessed time");
+    getter!(mtime, mtime, Option<Timespec>, "Returns the new last modified time");
+
+    /// Returns the handle of the opened file, if the change was made through it,
+    /// e.g. by `ftruncate`.
+    pub fn fh(&self) -> Option<FileHandle> {
+        self.fh.clone()
+    }
+
+    /// Returns true, if the mode or the ownership changes.
+    pub(crate) fn changes_ownership(&self) -> bool {
+        self.mode.is_some() || self.uid.is_some() || self.gid.is_some()
+    }
+
+}
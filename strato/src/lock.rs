@@ -0,0 +1,423 @@
+use std::cmp::{max, min};
+use std::collections::{HashMap, VecDeque};
+
+use libc::*;
+
+use parking_lot::Mutex;
+
+use futures::sync::oneshot;
+
+/// The kind of a POSIX record lock, as in `fcntl(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// A shared lock (F_RDLCK)
+    Read,
+    /// An exclusive lock (F_WRLCK)
+    Write,
+    /// No lock (F_UNLCK)
+    Unlock,
+}
+
+impl LockKind {
+
+    pub(crate) fn from_type(typ: u32) -> Option<Self> {
+        match typ as i32 {
+            F_RDLCK => Some(LockKind::Read),
+            F_WRLCK => Some(LockKind::Write),
+            F_UNLCK => Some(LockKind::Unlock),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_type(self) -> u32 {
+        match self {
+            LockKind::Read => F_RDLCK as u32,
+            LockKind::Write => F_WRLCK as u32,
+            LockKind::Unlock => F_UNLCK as u32,
+        }
+    }
+
+}
+
+/// A lock on the bytes `start` to `end` of a file, both inclusive.
+/// A lock until the end of the file has an `end` of `u64::MAX`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLock {
+    pub start: u64,
+    pub end: u64,
+    pub kind: LockKind,
+    /// The process holding the lock
+    pub pid: u32,
+    /// Identifies the holder of the lock. Locks of the same owner never conflict.
+    pub owner: u64,
+}
+
+impl FileLock {
+
+    fn overlaps(&self, other: &FileLock) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn conflicts(&self, other: &FileLock) -> bool {
+        self.owner != other.owner && self.overlaps(other)
+            && (self.kind == LockKind::Write || other.kind == LockKind::Write)
+    }
+
+}
+
+/// The number of interrupts, that are kept for requests, which do not wait for a lock yet
+const MAX_INTERRUPTED: usize = 64;
+
+/// The number of waiting owners, that are followed to find a deadlock, as in the kernel
+const MAX_DEADLOCK_STEPS: usize = 10;
+
+/// The kinds of locks on a node, which never conflict with each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum LockSpace {
+    /// `fcntl(2)` record locks, that belong to the lock owner of the kernel
+    Record,
+    /// `flock(2)` locks, that cover the whole file and belong to the open file
+    Flock,
+}
+
+type LockKey = (u64, LockSpace);
+
+/// The table of the locks of all nodes. Record locks are only kept for nodes, that do not
+/// manage their locks themselves, `flock(2)` locks are always kept here.
+#[derive(Debug, Default)]
+pub(crate) struct LockManager(Mutex<LockTable>);
+
+#[derive(Debug, Default)]
+struct LockTable {
+    // The locks held on every node
+    locks: HashMap<LockKey, Vec<FileLock>>,
+    // The requests waiting for a lock on every node.
+    // They are woken with `true` to retry and with `false`, if they were interrupted.
+    waiters: HashMap<LockKey, Vec<Waiter>>,
+    // The requests, that were interrupted before they waited for a lock
+    interrupted: VecDeque<u64>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    lock: FileLock,
+    owner: u64,
+    unique: u64,
+    sender: oneshot::Sender<bool>,
+}
+
+impl LockManager {
+
+    pub(crate) fn new() -> Self {
+        LockManager::default()
+    }
+
+    /// Returns a lock, that conflicts with `lock`, if there is any.
+    pub(crate) fn test(&self, ino: u64, space: LockSpace, lock: &FileLock) -> Option<FileLock> {
+        self.0.lock().locks.get(&(ino, space))
+            .and_then(|locks| locks.iter().find(|held| held.conflicts(lock)).cloned())
+    }
+
+    /// Acquires, changes or releases a lock. Fails with `EAGAIN`, if it conflicts with a lock
+    /// of another owner.
+    pub(crate) fn set(&self, ino: u64, space: LockSpace, lock: FileLock) -> Result<(), i32> {
+        self.0.lock().set((ino, space), lock).map_err(|_| EAGAIN)
+    }
+
+    /// Like `set`, but instead of failing, returns a receiver, that fires once the locks
+    /// of the node changed, so that acquiring the lock may be retried. The wait of request
+    /// `unique` can be cut short with `interrupt`, which fires the receiver with `false`.
+    /// Fails with `EDEADLK`, if the owner of a record lock would wait for an owner, that
+    /// waits for it in turn.
+    pub(crate) fn set_or_wait(&self, ino: u64, space: LockSpace, lock: FileLock, unique: u64)
+        -> Result<Option<oneshot::Receiver<bool>>, i32> {
+
+        let mut table = self.0.lock();
+        let owner = lock.owner;
+        let held = match table.set((ino, space), lock.clone()) {
+            Ok(()) => return Ok(None),
+            Err(held) => held,
+        };
+        if space == LockSpace::Record && table.deadlocks(owner, held.owner) {
+            return Err(EDEADLK);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        match table.interrupted.iter().position(|interrupted| *interrupted == unique) {
+            Some(i) => {
+                table.interrupted.remove(i);
+                let _ = sender.send(false);
+            },
+            None => {
+                let waiter = Waiter { lock, owner, unique, sender };
+                table.waiters.entry((ino, space)).or_default().push(waiter);
+            },
+        }
+        Ok(Some(receiver))
+    }
+
+    /// Interrupts the wait of request `unique` for a lock. The kernel may interrupt a request,
+    /// before it started waiting, so the interrupt is kept for a while, if it is not waiting.
+    pub(crate) fn interrupt(&self, unique: u64) {
+        let mut table = self.0.lock();
+
+        for waiters in table.waiters.values_mut() {
+            if let Some(i) = waiters.iter().position(|waiter| waiter.unique == unique) {
+                let _ = waiters.swap_remove(i).sender.send(false);
+                return;
+            }
+        }
+
+        if table.interrupted.len() == MAX_INTERRUPTED {
+            table.interrupted.pop_front();
+        }
+        table.interrupted.push_back(unique);
+    }
+
+    /// Releases all locks of `owner` on the node, when it closes the file.
+    /// A wait of the owner for a lock on the node is interrupted.
+    pub(crate) fn release_owner(&self, ino: u64, space: LockSpace, owner: u64) {
+        let mut table = self.0.lock();
+        let key = (ino, space);
+
+        if let Some(waiters) = table.waiters.get_mut(&key) {
+            while let Some(i) = waiters.iter().position(|waiter| waiter.owner == owner) {
+                let _ = waiters.swap_remove(i).sender.send(false);
+            }
+        }
+
+        let released = match table.locks.get_mut(&key) {
+            Some(locks) => {
+                let before = locks.len();
+                locks.retain(|held| held.owner != owner);
+                locks.len() != before
+            }
+            None => false,
+        };
+
+        if released {
+            table.wake(key);
+        }
+    }
+
+}
+
+impl LockTable {
+
+    fn set(&mut self, key: LockKey, lock: FileLock) -> Result<(), FileLock> {
+        if lock.kind != LockKind::Unlock {
+            let conflict = self.locks.get(&key)
+                .and_then(|locks| locks.iter().find(|held| held.conflicts(&lock)));
+            if let Some(held) = conflict {
+                return Err(held.clone());
+            }
+        }
+
+        let locks = self.locks.entry(key).or_default();
+
+        // The new lock replaces the locks of the same owner in its range, so these are cut
+        let mut remaining = Vec::with_capacity(locks.len() + 1);
+        for held in locks.drain(..) {
+            if held.owner != lock.owner || !held.overlaps(&lock) {
+                remaining.push(held);
+                continue;
+            }
+
+            if held.start < lock.start {
+                remaining.push(FileLock { end: lock.start - 1, ..held.clone() });
+            }
+            if held.end > lock.end {
+                remaining.push(FileLock { start: lock.end + 1, ..held.clone() });
+            }
+        }
+
+        if lock.kind != LockKind::Unlock {
+            // Merge with adjacent or overlapping locks of the same kind and owner
+            let mut merged = lock;
+            remaining.retain(|held| {
+                let touches = held.start <= merged.end.saturating_add(1)
+                    && merged.start <= held.end.saturating_add(1);
+                if held.owner == merged.owner && held.kind == merged.kind && touches {
+                    merged.start = min(merged.start, held.start);
+                    merged.end = max(merged.end, held.end);
+                    false
+                } else {
+                    true
+                }
+            });
+            remaining.push(merged);
+        }
+
+        *locks = remaining;
+        if locks.is_empty() {
+            self.locks.remove(&key);
+        }
+
+        self.wake(key);
+        Ok(())
+    }
+
+    /// Returns true, if `owner` waiting for a record lock of `blocker` closes a cycle of owners,
+    /// which wait for each other. Like the kernel, only the first conflicting lock of every
+    /// waiting owner is followed, and only for a few steps.
+    fn deadlocks(&self, owner: u64, blocker: u64) -> bool {
+        let mut blocker = blocker;
+        for _ in 0..MAX_DEADLOCK_STEPS {
+            if blocker == owner {
+                return true;
+            }
+            blocker = match self.blocker(blocker) {
+                Some(next) => next,
+                None => return false,
+            };
+        }
+        false
+    }
+
+    /// Returns the owner of a record lock, that blocks a waiting record lock of `owner`
+    fn blocker(&self, owner: u64) -> Option<u64> {
+        self.waiters.iter()
+            .filter(|((_, space), _)| *space == LockSpace::Record)
+            .flat_map(|(key, waiters)| waiters.iter().map(move |waiter| (key, waiter)))
+            .find(|(_, waiter)| waiter.owner == owner)
+            .and_then(|(key, waiter)| {
+                self.locks.get(key)?.iter().find(|held| held.conflicts(&waiter.lock))
+            })
+            .map(|held| held.owner)
+    }
+
+    /// Wakes all requests waiting for a lock on the node, so they try again
+    fn wake(&mut self, key: LockKey) {
+        if let Some(waiters) = self.waiters.remove(&key) {
+            for waiter in waiters {
+                let _ = waiter.sender.send(true);
+            }
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::LockSpace::*;
+
+    fn lock(start: u64, end: u64, kind: LockKind, owner: u64) -> FileLock {
+        FileLock { start, end, kind, pid: owner as u32, owner }
+    }
+
+    #[test]
+    fn conflicts() {
+        let manager = LockManager::new();
+
+        assert_eq!(manager.set(1, Record, lock(0, 99, LockKind::Read, 1)), Ok(()));
+        assert_eq!(manager.set(1, Record, lock(50, 149, LockKind::Read, 2)), Ok(()));
+        assert_eq!(manager.set(1, Record, lock(90, 95, LockKind::Write, 3)), Err(EAGAIN));
+        assert_eq!(manager.set(1, Record, lock(150, 199, LockKind::Write, 3)), Ok(()));
+
+        // Locks on other nodes and of the same owner do not conflict
+        assert_eq!(manager.set(2, Record, lock(0, 99, LockKind::Write, 3)), Ok(()));
+        assert_eq!(manager.set(1, Record, lock(0, 10, LockKind::Write, 1)), Ok(()));
+
+        assert_eq!(manager.test(1, Record, &lock(5, 5, LockKind::Read, 2)),
+                   Some(lock(0, 10, LockKind::Write, 1)));
+        assert_eq!(manager.test(1, Record, &lock(11, 20, LockKind::Read, 2)), None);
+    }
+
+    #[test]
+    fn split_and_release() {
+        let manager = LockManager::new();
+
+        manager.set(1, Record, lock(0, u64::MAX, LockKind::Write, 1)).unwrap();
+        manager.set(1, Record, lock(10, 19, LockKind::Unlock, 1)).unwrap();
+
+        assert_eq!(manager.test(1, Record, &lock(10, 19, LockKind::Write, 2)), None);
+        assert!(manager.test(1, Record, &lock(20, 20, LockKind::Read, 2)).is_some());
+
+        manager.release_owner(1, Record, 1);
+        assert_eq!(manager.test(1, Record, &lock(0, u64::MAX, LockKind::Write, 2)), None);
+    }
+
+    #[test]
+    fn waiting() {
+        let manager = LockManager::new();
+
+        manager.set(1, Record, lock(0, 9, LockKind::Write, 1)).unwrap();
+        let mut woken = manager.set_or_wait(1, Record, lock(0, 9, LockKind::Write, 2), 1)
+            .unwrap().unwrap();
+        let mut interrupted = manager.set_or_wait(1, Record, lock(0, 9, LockKind::Read, 3), 2)
+            .unwrap().unwrap();
+
+        // The waiting owner closes the file
+        manager.release_owner(1, Record, 3);
+        assert_eq!(interrupted.try_recv(), Ok(Some(false)));
+        assert_eq!(woken.try_recv(), Ok(None));
+
+        manager.release_owner(1, Record, 1);
+        assert_eq!(woken.try_recv(), Ok(Some(true)));
+        let acquired = manager.set_or_wait(1, Record, lock(0, 9, LockKind::Write, 2), 3);
+        assert!(matches!(acquired, Ok(None)));
+    }
+
+    #[test]
+    fn interrupt() {
+        let manager = LockManager::new();
+
+        manager.set(1, Record, lock(0, 9, LockKind::Write, 1)).unwrap();
+        let mut waiting = manager.set_or_wait(1, Record, lock(0, 9, LockKind::Write, 2), 7)
+            .unwrap().unwrap();
+        let mut other = manager.set_or_wait(1, Record, lock(5, 5, LockKind::Read, 3), 8)
+            .unwrap().unwrap();
+
+        manager.interrupt(7);
+        assert_eq!(waiting.try_recv(), Ok(Some(false)));
+        assert_eq!(other.try_recv(), Ok(None));
+
+        // An interrupt, that arrives before the request waits, ends the wait at once
+        manager.interrupt(9);
+        let mut early = manager.set_or_wait(1, Record, lock(0, 0, LockKind::Read, 4), 9)
+            .unwrap().unwrap();
+        assert_eq!(early.try_recv(), Ok(Some(false)));
+    }
+
+    #[test]
+    fn deadlock() {
+        let manager = LockManager::new();
+
+        manager.set(1, Record, lock(0, 9, LockKind::Write, 1)).unwrap();
+        manager.set(2, Record, lock(0, 9, LockKind::Write, 2)).unwrap();
+        manager.set(3, Record, lock(0, 9, LockKind::Write, 3)).unwrap();
+        let first = manager.set_or_wait(2, Record, lock(0, 0, LockKind::Write, 1), 1).unwrap();
+        let second = manager.set_or_wait(3, Record, lock(0, 0, LockKind::Write, 2), 2).unwrap();
+        assert!(first.is_some() && second.is_some());
+
+        // Owner 3 would wait for 1, which waits for 2, which waits for 3
+        assert_eq!(manager.set_or_wait(1, Record, lock(5, 5, LockKind::Read, 3), 3).err(),
+                   Some(EDEADLK));
+        // Owner 4 is not part of the cycle
+        assert!(manager.set_or_wait(1, Record, lock(5, 5, LockKind::Read, 4), 4).is_ok());
+
+        // Flocks belong to open files, which do not wait for each other
+        manager.set(1, Flock, lock(0, u64::MAX, LockKind::Write, 1)).unwrap();
+        manager.set(2, Flock, lock(0, u64::MAX, LockKind::Write, 2)).unwrap();
+        let waiting = manager.set_or_wait(2, Flock, lock(0, u64::MAX, LockKind::Write, 1), 5);
+        assert!(matches!(waiting, Ok(Some(_))));
+        assert!(manager.set_or_wait(1, Flock, lock(0, u64::MAX, LockKind::Write, 2), 6).is_ok());
+    }
+
+    #[test]
+    fn flock() {
+        let manager = LockManager::new();
+
+        // Record locks and flocks of a file do not conflict
+        manager.set(1, Record, lock(0, u64::MAX, LockKind::Write, 1)).unwrap();
+        assert_eq!(manager.set(1, Flock, lock(0, u64::MAX, LockKind::Write, 2)), Ok(()));
+        assert_eq!(manager.set(1, Flock, lock(0, u64::MAX, LockKind::Read, 3)), Err(EAGAIN));
+
+        manager.release_owner(1, Flock, 2);
+        assert_eq!(manager.set(1, Flock, lock(0, u64::MAX, LockKind::Read, 3)), Ok(()));
+        assert!(manager.test(1, Record, &lock(0, 0, LockKind::Read, 3)).is_some());
+    }
+
+}
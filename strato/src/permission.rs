@@ -0,0 +1,173 @@
+use libc::*;
+
+use fuse_strato::FileAttr;
+
+use crate::error::NodeError;
+use crate::link::AttrChanges;
+
+/// Selects, who enforces the permission bits of the nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PermissionMode {
+    /// Permissions are not checked at all. Nodes may check them on their own.
+    #[default]
+    Unchecked,
+    /// Strato checks the mode bits against the credentials of each request, before
+    /// handing it to the node. Like in POSIX, reading and writing is checked, when a file
+    /// is opened. Denied requests fail with `NodeError::PermissionDenied`.
+    Checked,
+    /// The kernel checks the mode bits itself, by mounting with `default_permissions`.
+    /// This saves the extra `read_attributes` calls, but uses the attributes cached by the kernel.
+    Kernel,
+}
+
+/// Returns the access mask needed to open a file with `flags`.
+pub(crate) fn open_mask(flags: u32) -> u32 {
+    let mask = match flags as i32 & O_ACCMODE {
+        O_RDONLY => R_OK,
+        O_WRONLY => W_OK,
+        _ => R_OK | W_OK,
+    };
+
+    if flags as i32 & O_TRUNC != 0 {
+        (mask | W_OK) as u32
+    } else {
+        mask as u32
+    }
+}
+
+/// Evaluates the POSIX mode bits of `attr` for a caller with `uid` and `gid`.
+/// `mask` is a combination of `R_OK`, `W_OK` and `X_OK`, as passed to `access(2)`.
+///
+/// Only the primary group of the caller is considered, since FUSE does not pass
+/// the supplementary groups.
+pub(crate) fn check(uid: u32, gid: u32, attr: &FileAttr, mask: u32) -> Result<(), NodeError> {
+    let mask = mask & (R_OK | W_OK | X_OK) as u32;
+    let perm = u32::from(attr.perm);
+
+    // Root may read and write everything, but only execute, what is executable by anyone
+    if uid == 0 {
+        if mask & X_OK as u32 == 0 || perm & 0o111 != 0 {
+            return Ok(());
+        }
+        return Err(NodeError::PermissionDenied);
+    }
+
+    let granted = if uid == attr.uid {
+        (perm >> 6) & 0o7
+    } else if gid == attr.gid {
+        (perm >> 3) & 0o7
+    } else {
+        perm & 0o7
+    };
+
+    if granted & mask == mask {
+        Ok(())
+    } else {
+        Err(NodeError::PermissionDenied)
+    }
+}
+
+/// Checks, whether a caller with `uid` and `gid` may apply `changes` to a node with `attr`.
+/// Only the owner may change the mode and only root may give a node away.
+/// Changing the size needs write access, changing the times either write access or ownership.
+pub(crate) fn check_changes(uid: u32, gid: u32, attr: &FileAttr, changes: &AttrChanges)
+    -> Result<(), NodeError> {
+
+    if uid == 0 {
+        return Ok(());
+    }
+
+    if changes.changes_ownership() {
+        let gives_away = changes.uid().map(|new| new != attr.uid).unwrap_or(false);
+        let foreign_group = changes.gid().map(|new| new != attr.gid && new != gid)
+            .unwrap_or(false);
+
+        if uid != attr.uid || gives_away || foreign_group {
+            return Err(NodeError::NotPermitted);
+        }
+    }
+
+    if changes.size().is_some() {
+        check(uid, gid, attr, W_OK as u32)?;
+    }
+
+    if (changes.atime().is_some() || changes.mtime().is_some()) && uid != attr.uid {
+        check(uid, gid, attr, W_OK as u32)?;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use fuse_strato::FileType;
+
+    fn attr(perm: u16) -> FileAttr {
+        let epoch = UNIX_EPOCH;
+        FileAttr {
+            ino: 2,
+            size: 0,
+            blocks: 0,
+            atime: epoch,
+            mtime: epoch,
+            ctime: epoch,
+            crtime: epoch,
+            kind: FileType::RegularFile,
+            perm,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            flags: 0,
+            blksize: 4096,
+        }
+    }
+
+    #[test]
+    fn mode_bits() {
+        let file = attr(0o640);
+        let (r, w, x) = (R_OK as u32, W_OK as u32, X_OK as u32);
+
+        // Owner, group and others
+        assert!(check(1000, 100, &file, r | w).is_ok());
+        assert!(check(1000, 100, &file, x).is_err());
+        assert!(check(1001, 100, &file, r).is_ok());
+        assert!(check(1001, 100, &file, w).is_err());
+        assert!(check(1001, 101, &file, r).is_err());
+
+        // Existence only
+        assert!(check(1001, 101, &file, F_OK as u32).is_ok());
+
+        // Root can not execute files without any execute bit
+        assert!(check(0, 0, &file, r | w).is_ok());
+        assert!(check(0, 0, &file, x).is_err());
+        assert!(check(0, 0, &attr(0o700), x).is_ok());
+    }
+
+    #[test]
+    fn changes() {
+        let file = attr(0o664);
+        let chmod = AttrChanges::new(Some(0o600), None, None, None, None, None, None);
+        let chown = AttrChanges::new(None, Some(1001), None, None, None, None, None);
+        let truncate = AttrChanges::new(None, None, None, Some(0), None, None, None);
+
+        assert!(check_changes(1000, 100, &file, &chmod).is_ok());
+        assert!(check_changes(1001, 100, &file, &chmod).is_err());
+        assert!(check_changes(1000, 100, &file, &chown).is_err());
+        assert!(check_changes(0, 0, &file, &chown).is_ok());
+        assert!(check_changes(1001, 100, &file, &truncate).is_ok());
+        assert!(check_changes(1001, 101, &file, &truncate).is_err());
+    }
+
+    #[test]
+    fn open_flags() {
+        assert_eq!(open_mask(O_RDONLY as u32), R_OK as u32);
+        assert_eq!(open_mask(O_WRONLY as u32), W_OK as u32);
+        assert_eq!(open_mask(O_RDWR as u32), (R_OK | W_OK) as u32);
+        assert_eq!(open_mask((O_RDONLY | O_TRUNC) as u32), (R_OK | W_OK) as u32);
+    }
+
+}
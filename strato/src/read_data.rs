@@ -0,0 +1,22 @@
+use std::os::unix::io::RawFd;
+
+/// The data returned by `File::read`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadData {
+    /// The data itself
+    Bytes(Vec<u8>),
+    /// The data is read from the file descriptor `fd`, starting at `offset`, up to the size of
+    /// the request. It is spliced into the kernel without passing through userspace, if the
+    /// engine was started with `Engine::splice_write`. The descriptor must stay open, until
+    /// the read is answered.
+    Splice {
+        fd: RawFd,
+        offset: i64,
+    },
+}
+
+impl From<Vec<u8>> for ReadData {
+    fn from(data: Vec<u8>) -> Self {
+        ReadData::Bytes(data)
+    }
+}
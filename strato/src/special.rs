@@ -0,0 +1,59 @@
+use libc::*;
+
+use fuse_strato::FileType;
+
+/// The kinds of nodes, that are neither files, directories nor links.
+/// Their content is never served by the file system, the kernel handles them itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKind {
+    /// Named pipe (S_IFIFO)
+    NamedPipe,
+    /// Character device (S_IFCHR)
+    CharDevice,
+    /// Block device (S_IFBLK)
+    BlockDevice,
+    /// Unix domain socket (S_IFSOCK)
+    Socket,
+}
+
+impl SpecialKind {
+
+    /// Returns the kind encoded in the file type bits of `mode`, as passed to `mknod`.
+    /// Returns `None` for regular files, directories and links.
+    pub fn from_mode(mode: u32) -> Option<Self> {
+        match mode & S_IFMT {
+            S_IFIFO => Some(SpecialKind::NamedPipe),
+            S_IFCHR => Some(SpecialKind::CharDevice),
+            S_IFBLK => Some(SpecialKind::BlockDevice),
+            S_IFSOCK => Some(SpecialKind::Socket),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_file_type(self) -> FileType {
+        match self {
+            SpecialKind::NamedPipe => FileType::NamedPipe,
+            SpecialKind::CharDevice => FileType::CharDevice,
+            SpecialKind::BlockDevice => FileType::BlockDevice,
+            SpecialKind::Socket => FileType::Socket,
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_mode() {
+        assert_eq!(SpecialKind::from_mode(S_IFIFO | 0o644), Some(SpecialKind::NamedPipe));
+        assert_eq!(SpecialKind::from_mode(S_IFCHR | 0o600), Some(SpecialKind::CharDevice));
+        assert_eq!(SpecialKind::from_mode(S_IFBLK), Some(SpecialKind::BlockDevice));
+        assert_eq!(SpecialKind::from_mode(S_IFSOCK | 0o777), Some(SpecialKind::Socket));
+        assert_eq!(SpecialKind::from_mode(S_IFREG | 0o644), None);
+        assert_eq!(SpecialKind::from_mode(S_IFDIR | 0o755), None);
+    }
+
+}
@@ -0,0 +1,43 @@
+/// The usage statistics of the file system, as reported by `statfs(2)` and `df`.
+/// Block counts are in units of `fragment_size`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsStats {
+    /// The total number of blocks
+    pub blocks: u64,
+    /// The number of free blocks
+    pub blocks_free: u64,
+    /// The number of blocks available to unprivileged users
+    pub blocks_available: u64,
+    /// The total number of inodes
+    pub files: u64,
+    /// The number of free inodes
+    pub files_free: u64,
+    /// The preferred block size for I/O
+    pub block_size: u32,
+    /// The maximum length of a file name
+    pub name_length: u32,
+    /// The fragment size, which is the unit of the block counts
+    pub fragment_size: u32,
+}
+
+/// The number of free inodes reported by default, since inos never run out
+const DEFAULT_FREE_FILES: u64 = u32::MAX as u64;
+
+impl FsStats {
+
+    /// Creates the default statistics for a file system with `used_files` nodes.
+    /// No blocks are reported, since strato does not know, where the data lives.
+    pub(crate) fn new(used_files: u64) -> Self {
+        FsStats {
+            blocks: 0,
+            blocks_free: 0,
+            blocks_available: 0,
+            files: used_files + DEFAULT_FREE_FILES,
+            files_free: DEFAULT_FREE_FILES,
+            block_size: 4096,
+            name_length: 255,
+            fragment_size: 4096,
+        }
+    }
+
+}
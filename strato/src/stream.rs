@@ -0,0 +1,190 @@
+use std::cmp::min;
+
+use fuse_strato::ReplyData;
+
+use bytes::Bytes;
+
+use tokio::prelude::*;
+use tokio::codec::{FramedRead, BytesCodec};
+use futures::future::{self, Loop};
+use futures::sync::mpsc::{self, UnboundedSender};
+
+use crate::handler::{Handle, HandleDispatcher::*};
+use crate::controller::Request;
+use crate::error::{FileError, NodeError};
+
+type ChunkStream = Box<dyn Stream<Item=Vec<u8>, Error=FileError> + Send>;
+
+/// The data source of an opened `StreamFile`.
+/// It can be created either from a `Stream` of byte chunks or from an `AsyncRead`.
+pub struct FileStream(ChunkStream);
+
+impl FileStream {
+
+    pub fn from_stream<S: 'static>(stream: S) -> Self
+        where S: Stream<Item=Vec<u8>, Error=FileError> + Send {
+        FileStream(Box::new(stream))
+    }
+
+    pub fn from_reader<R: 'static>(reader: R) -> Self
+        where R: AsyncRead + Send {
+        FileStream(Box::new(FramedRead::new(reader, BytesCodec::new())
+            .map(|bytes| bytes.to_vec())
+            .map_err(|_| FileError::new(NodeError::IOError))))
+    }
+
+}
+
+/// Defines, what happens if a read requests data before the current position of the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekPolicy {
+    /// The stream is opened again and read up to the requested offset.
+    Reopen,
+    /// The read is answered with `EIO`.
+    Error,
+}
+
+
+/// A read request, that is forwarded to the worker of an open stream.
+#[derive(Debug)]
+pub(crate) struct StreamRead {
+    pub(crate) offset: i64,
+    pub(crate) size: u32,
+    pub(crate) reply: ReplyData,
+}
+
+/// Spawns a worker onto the current runtime, that owns the stream and serves the reads
+/// sent to the returned channel in order. The stream is dropped with the channel.
+pub(crate) fn spawn_stream_worker(handle: Handle, req: Request, policy: SeekPolicy,
+                                  stream: FileStream) -> UnboundedSender<StreamRead> {
+
+    let (sender, receiver) = mpsc::unbounded::<StreamRead>();
+
+    let state = StreamState {
+        handle,
+        req,
+        policy,
+        stream: Some(stream.0),
+        position: 0,
+        buffer: Vec::new(),
+        eof: false,
+        failure: None,
+    };
+
+    tokio::executor::spawn(receiver.fold(state, |state, read| state.serve(read)).map(|_| ()));
+    sender
+}
+
+
+struct StreamState {
+    handle: Handle,
+    req: Request,
+    policy: SeekPolicy,
+
+    stream: Option<ChunkStream>,
+    // The offset of the first byte in the buffer
+    position: u64,
+    buffer: Vec<u8>,
+    eof: bool,
+    failure: Option<FileError>,
+}
+
+impl StreamState {
+
+    fn serve(self, read: StreamRead) -> Box<dyn Future<Item=StreamState, Error=()> + Send> {
+
+        if (read.offset as u64) >= self.position {
+            return self.fill(read);
+        }
+
+        Box::new(self.reopen().then(move |result| {
+            match result {
+                Ok(state) => state.fill(read),
+                Err((state, error)) => {
+                    read.reply.error(error.get_libc_code());
+                    Box::new(future::ok(state))
+                }
+            }
+        }))
+    }
+
+    /// Reads from the stream, until the requested range is in the buffer, and replies with it
+    fn fill(self, read: StreamRead) -> Box<dyn Future<Item=StreamState, Error=()> + Send> {
+
+        let offset = read.offset as u64;
+        let size = read.size as usize;
+        let reply = read.reply;
+
+        Box::new(future::loop_fn(self, move |mut state| {
+
+            // Drop everything before the requested offset, it will not be read again
+            if offset > state.position {
+                let skip = min((offset - state.position) as usize, state.buffer.len());
+                state.buffer.drain(..skip);
+                state.position += skip as u64;
+            }
+
+            let filled = state.position == offset && state.buffer.len() >= size;
+            if filled || state.eof || state.failure.is_some() {
+                return future::Either::A(future::ok(Loop::Break(state)));
+            }
+
+            let stream = state.stream.take().unwrap();
+            future::Either::B(stream.into_future().then(move |result| {
+                match result {
+                    Ok((Some(chunk), stream)) => {
+                        state.buffer.extend(chunk);
+                        state.stream = Some(stream);
+                    },
+                    Ok((None, stream)) => {
+                        state.eof = true;
+                        state.stream = Some(stream);
+                    },
+                    Err((error, _)) => {
+                        state.failure = Some(error);
+                    },
+                }
+                future::ok(Loop::Continue(state))
+            }))
+
+        }).map(move |state| {
+
+            match state.failure {
+                Some(ref error) => reply.error(error.get_libc_code()),
+                None if state.position != offset => reply.data(Bytes::new()),
+                None => reply.data(&state.buffer[..min(size, state.buffer.len())]),
+            }
+            state
+
+        }))
+    }
+
+    /// Opens the stream again, to read from its start
+    fn reopen(self) -> Box<dyn Future<Item=StreamState, Error=(StreamState, FileError)> + Send> {
+
+        if self.policy == SeekPolicy::Error {
+            return Box::new(future::err((self, FileError::new(NodeError::IOError))));
+        }
+
+        let opened = match self.handle.dispatch() {
+            Streamed(ref file) => file.open_stream(self.req.clone()),
+            _ => Box::new(future::err(FileError::new(FileError::IsDirectory))),
+        };
+
+        Box::new(opened.then(move |result| {
+            let mut state = self;
+            match result {
+                Ok(stream) => {
+                    state.stream = Some(stream.0);
+                    state.position = 0;
+                    state.buffer.clear();
+                    state.eof = false;
+                    state.failure = None;
+                    Ok(state)
+                }
+                Err(error) => Err((state, error)),
+            }
+        }))
+    }
+
+}
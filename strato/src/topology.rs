@@ -0,0 +1,395 @@
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf, Component};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use futures::future::{self, Future, Loop};
+
+use crate::Registry;
+use crate::file_handle::FhTable;
+use crate::handler::{Handle, HandleDispatcher::*};
+use crate::controller::Request;
+use crate::error::NodeError;
+
+/// The ino of the root directory, which is always the first node added to the engine
+pub(crate) const ROOT_INO: u64 = 1;
+
+/// Keeps track of the directory entries, that refer to each node.
+/// Entries created through file system operations are registered automatically, as well as
+/// every entry returned by a lookup.
+///
+/// Only the entries of nodes created through the file system are all known, since a directory
+/// may serve any handle without registering it. Only these nodes are counted exactly and torn
+/// down, once they lose their last entry.
+#[derive(Debug)]
+pub(crate) struct Topology(RwLock<TopologyInner>);
+
+#[derive(Debug, Default)]
+struct TopologyInner {
+    // The entries of every directory, mapping names to inos
+    children: HashMap<u64, HashMap<String, u64>>,
+    // The entries, that refer to a node, as pairs of parent ino and name
+    names: HashMap<u64, Vec<(u64, String)>>,
+    // The nodes, that are known to be directories
+    directories: HashSet<u64>,
+    // The number of subdirectories of every directory
+    subdirs: HashMap<u64, u32>,
+    // Nodes, whose entries are all known, since they were created through the file system.
+    // For directories, this includes the entries of their subdirectories.
+    complete: HashSet<u64>,
+    // Nodes without any entry, that are kept alive by open file handles
+    orphans: HashSet<u64>,
+}
+
+impl Topology {
+
+    pub(crate) fn new() -> Self {
+        Topology(RwLock::new(TopologyInner::default()))
+    }
+
+    /// Registers the entry `name` in directory `parent`.
+    /// Returns the ino of a node, that lost its last entry by being replaced.
+    pub(crate) fn link(&self, parent: u64, name: &str, child: u64, is_dir: bool) -> Option<u64> {
+        if name == "." || name == ".." {
+            return None;
+        }
+        self.0.write().link(parent, name, child, is_dir)
+    }
+
+    /// Registers the entry `name` of a node, that was just created in directory `parent`.
+    /// Returns the ino of a node, that lost its last entry by being replaced.
+    pub(crate) fn created(&self, parent: u64, name: &str, child: u64, is_dir: bool)
+        -> Option<u64> {
+        let mut inner = self.0.write();
+        let replaced = inner.link(parent, name, child, is_dir);
+        inner.complete.insert(child);
+        replaced
+    }
+
+    /// Removes the entry `name` from directory `parent`.
+    /// Returns the ino of the node, if this was its last entry and its entries are all known.
+    pub(crate) fn unlink(&self, parent: u64, name: &str) -> Option<u64> {
+        self.0.write().unlink(parent, name)
+    }
+
+    /// Removes the entry `name` from directory `parent`, after it got lost. Its node is kept
+    /// as an orphan, if this was its last entry, so it is not torn down while still in use.
+    pub(crate) fn lost(&self, parent: u64, name: &str) {
+        let mut inner = self.0.write();
+        if let Some(child) = inner.unlink(parent, name) {
+            inner.orphans.insert(child);
+        }
+    }
+
+    /// Moves an entry, replacing an existing entry at the destination.
+    /// Returns the ino of a node, that lost its last entry by being replaced.
+    pub(crate) fn rename(&self, parent: u64, name: &str, new_parent: u64, new_name: &str)
+        -> Option<u64> {
+
+        let mut inner = self.0.write();
+        let child = match inner.children.get(&parent).and_then(|c| c.get(name)) {
+            Some(child) => *child,
+            None => return None,
+        };
+        let is_dir = inner.directories.contains(&child);
+
+        // Link first, so that the moved node never runs out of entries
+        let replaced = inner.link(new_parent, new_name, child, is_dir);
+        inner.unlink(parent, name);
+        replaced
+    }
+
+    /// Returns the directory, that contains the first known entry of a node.
+    /// Since directories can not be hard linked, this is the parent of a directory.
+    pub(crate) fn parent(&self, ino: u64) -> Option<u64> {
+        self.0.read().names.get(&ino)
+            .and_then(|names| names.first())
+            .map(|(parent, _)| *parent)
+    }
+
+    /// Returns all known entries of a node, as pairs of parent ino and name.
+    pub(crate) fn names(&self, ino: u64) -> Vec<(u64, String)> {
+        self.0.read().names.get(&ino).cloned().unwrap_or_else(Vec::new)
+    }
+
+    /// Returns the absolute path of a node, following its first known entry up to the root.
+    /// Returns `None`, if the node is not connected to the root by known entries.
+    pub(crate) fn path(&self, ino: u64) -> Option<PathBuf> {
+        let inner = self.0.read();
+        let mut components = Vec::new();
+        let mut current = ino;
+
+        while current != ROOT_INO {
+            let (parent, name) = inner.names.get(&current)?.first()?;
+            components.push(name.clone());
+            current = *parent;
+
+            // Only directories can be on the way up and these can not form cycles,
+            // unless the topology is corrupted.
+            if components.len() > inner.directories.len() + 1 {
+                return None;
+            }
+        }
+
+        let mut path = PathBuf::from("/");
+        path.extend(components.iter().rev());
+        Some(path)
+    }
+
+    /// Resolves an absolute path to the node it refers to. Entries, that are not known yet,
+    /// are looked up in their directory.
+    pub(crate) fn resolve(topology: Arc<Topology>, path: &Path, registry: Registry)
+        -> Box<dyn Future<Item=Handle, Error=NodeError> + Send> {
+
+        // The components are taken from the back, with `None` going up to the parent
+        let components: Vec<Option<String>> = path.components().rev()
+            .filter_map(|component| match component {
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+                Component::ParentDir => Some(None),
+                Component::Normal(name) => Some(Some(name.to_string_lossy().to_string())),
+            })
+            .collect();
+
+        let lookup_registry = registry.clone();
+        let walk = future::lazy(move || future::loop_fn((ROOT_INO, components),
+            move |(ino, mut components)| {
+                let name = match components.pop() {
+                    None => return future::Either::A(future::ok(Loop::Break(ino))),
+                    Some(None) => {
+                        let parent = topology.parent(ino).unwrap_or(ino);
+                        return future::Either::A(future::ok(Loop::Continue((parent, components))));
+                    }
+                    Some(Some(name)) => name,
+                };
+
+                let known = topology.0.read().children.get(&ino)
+                    .and_then(|children| children.get(&name).cloned());
+                if let Some(child) = known {
+                    return future::Either::A(future::ok(Loop::Continue((child, components))));
+                }
+
+                let handle = match lookup_registry.get(ino) {
+                    Some(handle) => handle,
+                    None => return future::Either::A(future::err(NodeError::NoSuchEntry)),
+                };
+                let lookup = match handle.dispatch() {
+                    Dir(ref dir) => dir.lookup(Request::internal(), name.clone()),
+                    _ => return future::Either::A(future::err(NodeError::NoSuchEntry)),
+                };
+
+                let topology = topology.clone();
+                future::Either::B(lookup.map(move |entry| {
+                    let handle = entry.get_handle();
+                    let (child, is_dir) = (handle.get_ino(), handle.is_dir());
+                    topology.link(ino, &name, child, is_dir);
+                    Loop::Continue((child, components))
+                }))
+            }));
+
+        Box::new(walk.and_then(move |ino| {
+            registry.get(ino).ok_or(NodeError::NoSuchEntry)
+        }))
+    }
+
+    /// Returns the number of entries referring to a node.
+    /// Directories count their own `.` and the `..` of each subdirectory.
+    ///
+    /// Unless the node was created through the file system, the count `reported` by its
+    /// directory is used. Without it, a directory reports 1, which tells tools like `find`,
+    /// that the number of its subdirectories is not known, and a file its known entries.
+    pub(crate) fn nlink(&self, ino: u64, is_dir: bool, reported: Option<u32>) -> u32 {
+        let inner = self.0.read();
+        let known = inner.names.get(&ino).map(|names| names.len()).unwrap_or(0) as u32;
+
+        if inner.orphans.contains(&ino) {
+            0
+        } else if inner.complete.contains(&ino) && is_dir {
+            2 + inner.subdirs.get(&ino).cloned().unwrap_or(0)
+        } else if inner.complete.contains(&ino) {
+            known
+        } else if let Some(reported) = reported {
+            reported
+        } else if is_dir {
+            1
+        } else {
+            // Nodes, that were never looked up, have at least the entry they were found by
+            max(1, known)
+        }
+    }
+
+    /// Tears down a node, that lost its last entry. If the node is still opened, this is
+    /// deferred until its last file handle is released.
+    pub(crate) fn collect(&self, ino: u64, registry: &Registry, fh_table: &FhTable) {
+        if fh_table.is_open(ino) {
+            self.0.write().orphans.insert(ino);
+        } else {
+            Topology::destroy(ino, registry);
+        }
+    }
+
+    /// Tears down an orphaned node, once it is not opened anymore.
+    pub(crate) fn released(&self, ino: u64, registry: &Registry, fh_table: &FhTable) {
+        if !self.0.read().orphans.contains(&ino) || fh_table.is_open(ino) {
+            return;
+        }
+
+        if self.0.write().orphans.remove(&ino) {
+            Topology::destroy(ino, registry);
+        }
+    }
+
+    fn destroy(ino: u64, registry: &Registry) {
+        let handle = registry.remove(ino);
+        if let Some(handle) = handle {
+            handle.destroy();
+        }
+    }
+
+}
+
+impl TopologyInner {
+
+    fn link(&mut self, parent: u64, name: &str, child: u64, is_dir: bool) -> Option<u64> {
+
+        let replaced = match self.children.get(&parent).and_then(|c| c.get(name)) {
+            Some(old) if *old == child => return None,
+            Some(_) => self.unlink(parent, name),
+            None => None,
+        };
+
+        self.children.entry(parent).or_insert_with(HashMap::new)
+            .insert(name.to_string(), child);
+        self.names.entry(child).or_insert_with(Vec::new).push((parent, name.to_string()));
+        self.orphans.remove(&child);
+
+        if is_dir {
+            self.directories.insert(child);
+            *self.subdirs.entry(parent).or_insert(0) += 1;
+        }
+
+        replaced
+    }
+
+    fn unlink(&mut self, parent: u64, name: &str) -> Option<u64> {
+
+        let child = self.children.get_mut(&parent).and_then(|c| c.remove(name))?;
+
+        if self.directories.contains(&child) {
+            if let Some(count) = self.subdirs.get_mut(&parent) {
+                *count = count.saturating_sub(1);
+            }
+        }
+
+        let remaining = match self.names.get_mut(&child) {
+            Some(names) => {
+                names.retain(|(p, n)| !(*p == parent && n == name));
+                names.len()
+            }
+            None => 0,
+        };
+
+        if remaining > 0 {
+            return None;
+        }
+
+        // Other directories may still serve a node, whose entries are not all known
+        self.names.remove(&child);
+        if !self.complete.remove(&child) {
+            return None;
+        }
+        self.children.remove(&child);
+        self.subdirs.remove(&child);
+        self.directories.remove(&child);
+        Some(child)
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_links() {
+        let topology = Topology::new();
+
+        assert_eq!(topology.created(1, "a", 2, false), None);
+        assert_eq!(topology.link(3, "b", 2, false), None);
+        assert_eq!(topology.nlink(2, false, None), 2);
+
+        // Registering the same entry twice does not count
+        assert_eq!(topology.link(1, "a", 2, false), None);
+        assert_eq!(topology.nlink(2, false, Some(5)), 2);
+
+        assert_eq!(topology.unlink(1, "a"), None);
+        assert_eq!(topology.nlink(2, false, None), 1);
+        assert_eq!(topology.unlink(3, "b"), Some(2));
+
+        // A lost entry leaves an orphan behind
+        topology.created(1, "c", 5, false);
+        topology.lost(1, "c");
+        assert_eq!(topology.nlink(5, false, None), 0);
+    }
+
+    #[test]
+    fn unknown_entries() {
+        let topology = Topology::new();
+
+        // The node may be served by further directories, that were not looked up
+        topology.link(1, "a", 2, false);
+        topology.link(3, "b", 2, false);
+        assert_eq!(topology.nlink(2, false, None), 2);
+        assert_eq!(topology.nlink(2, false, Some(3)), 3);
+        assert_eq!(topology.unlink(1, "a"), None);
+        assert_eq!(topology.unlink(3, "b"), None);
+        assert_eq!(topology.nlink(2, false, None), 1);
+
+        topology.link(1, "dir", 4, true);
+        assert_eq!(topology.nlink(4, true, None), 1);
+        assert_eq!(topology.nlink(4, true, Some(7)), 7);
+    }
+
+    #[test]
+    fn directories() {
+        let topology = Topology::new();
+
+        topology.created(1, "dir", 2, true);
+        topology.created(1, "other", 3, true);
+        topology.created(2, "file", 4, false);
+        topology.link(2, ".", 2, true);
+        topology.link(2, "..", 1, true);
+
+        // The subdirectories of the root are not all known
+        assert_eq!(topology.nlink(1, true, None), 1);
+        assert_eq!(topology.nlink(2, true, None), 2);
+        assert_eq!(topology.parent(2), Some(1));
+        assert_eq!(topology.parent(1), None);
+
+        // Moving a directory moves its `..` entry as well
+        assert_eq!(topology.rename(1, "other", 2, "moved"), None);
+        assert_eq!(topology.nlink(2, true, None), 3);
+        assert_eq!(topology.nlink(3, true, None), 2);
+        assert_eq!(topology.parent(3), Some(2));
+        assert_eq!(topology.path(4), Some(PathBuf::from("/dir/file")));
+        assert_eq!(topology.path(3), Some(PathBuf::from("/dir/moved")));
+        assert_eq!(topology.path(1), Some(PathBuf::from("/")));
+        assert_eq!(topology.path(5), None);
+    }
+
+    #[test]
+    fn rename_replaces() {
+        let topology = Topology::new();
+
+        topology.created(1, "a", 2, false);
+        topology.created(1, "b", 3, false);
+
+        assert_eq!(topology.rename(1, "a", 1, "b"), Some(3));
+        assert_eq!(topology.nlink(2, false, None), 1);
+        assert_eq!(topology.unlink(1, "a"), None);
+        assert_eq!(topology.unlink(1, "b"), Some(2));
+    }
+
+}
@@ -1,4 +1,7 @@
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use time::Timespec;
 
 /// The thread safe generator if Inos
 /// Used when spawning a new handler
@@ -19,4 +22,48 @@ impl InoGenerator {
         self.next_ino.fetch_add(1, Ordering::SeqCst)
     }
 
-}
\ No newline at end of file
+}
+
+/// Converts a point in time into the format expected by the FUSE replies
+pub(crate) fn to_system_time(time: Timespec) -> SystemTime {
+    let nanos = Duration::new(0, time.nsec as u32);
+    if time.sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(time.sec as u64) + nanos
+    } else {
+        UNIX_EPOCH - Duration::from_secs(time.sec.wrapping_neg() as u64) + nanos
+    }
+}
+
+/// Converts a point in time, as sent by the kernel, into the format used by strato
+pub(crate) fn to_timespec(time: SystemTime) -> Timespec {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => Timespec::new(since.as_secs() as i64, since.subsec_nanos() as i32),
+        Err(before) => {
+            let before = before.duration();
+            let nanos = before.subsec_nanos() as i32;
+            // The nanoseconds always count forward
+            if nanos == 0 {
+                Timespec::new(-(before.as_secs() as i64), 0)
+            } else {
+                Timespec::new(-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        for time in &[Timespec::new(0, 0), Timespec::new(1_500_000_000, 250),
+                      Timespec::new(-1, 500_000_000), Timespec::new(-20, 0)] {
+            assert_eq!(to_timespec(to_system_time(*time)), *time);
+        }
+        assert_eq!(to_system_time(Timespec::new(-1, 500_000_000)),
+                   UNIX_EPOCH - Duration::from_millis(500));
+    }
+
+}
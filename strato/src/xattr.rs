@@ -0,0 +1,72 @@
+use libc::*;
+
+use fuse_strato::ReplyXattr;
+
+/// Selects, how `set_xattr` treats existing attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XattrMode {
+    /// Creates the attribute or replaces its value.
+    Any,
+    /// Fails with `NodeError::AttributeExists`, if the attribute exists (XATTR_CREATE).
+    Create,
+    /// Fails with `NodeError::NoSuchAttribute`, if the attribute does not exist (XATTR_REPLACE).
+    Replace,
+}
+
+impl XattrMode {
+
+    pub(crate) fn from_flags(flags: u32) -> Self {
+        if flags & XATTR_CREATE as u32 != 0 {
+            XattrMode::Create
+        } else if flags & XATTR_REPLACE as u32 != 0 {
+            XattrMode::Replace
+        } else {
+            XattrMode::Any
+        }
+    }
+
+}
+
+/// Encodes the names of the attributes as a list of null terminated strings.
+pub(crate) fn encode_list(names: Vec<String>) -> Vec<u8> {
+    let mut list = Vec::new();
+    for name in names {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+    }
+    list
+}
+
+/// Replies with the value, following the size probing protocol of `getxattr(2)`:
+/// A `size` of 0 asks for the length of the value only. If `size` is too small
+/// to hold the value, `ERANGE` is sent.
+pub(crate) fn reply_sized(reply: ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        assert_eq!(XattrMode::from_flags(0), XattrMode::Any);
+        assert_eq!(XattrMode::from_flags(XATTR_CREATE as u32), XattrMode::Create);
+        assert_eq!(XattrMode::from_flags(XATTR_REPLACE as u32), XattrMode::Replace);
+    }
+
+    #[test]
+    fn list() {
+        let names = vec!["user.hash".to_string(), "user.origin".to_string()];
+        assert_eq!(encode_list(names), b"user.hash\0user.origin\0".to_vec());
+        assert_eq!(encode_list(vec![]), Vec::<u8>::new());
+    }
+
+}